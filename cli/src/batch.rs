@@ -0,0 +1,119 @@
+use crate::{finish, read_image, relax, sample, RunOptions};
+use anyhow::{anyhow, bail, Result};
+use inkdrop::document::Document;
+use log::info;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args)]
+pub struct Options {
+    /// directory or glob pattern such as `frames/*.png`, in the names of all outputs `{stem}` is
+    /// replaced with the name of each input without extension and `{index}` with its position
+    #[arg(long, short)]
+    input: String,
+
+    /// treat the inputs as frames and start each of them from the points of the previous one
+    #[arg(long)]
+    animate: bool,
+
+    #[command(flatten)]
+    run: RunOptions,
+}
+
+/// Images in directory `pattern` or matching the glob `pattern`, sorted by name.
+fn inputs(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = if Path::new(pattern).is_dir() {
+        std::fs::read_dir(pattern)?
+            .map(|entry| Ok(entry?.path()))
+            .filter(|path| match path {
+                Ok(path) => image::ImageFormat::from_path(path).is_ok(),
+                Err(_) => true,
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?
+    };
+
+    if paths.is_empty() {
+        bail!("No images found for `{}`", pattern);
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+fn expand(template: &Path, input: &Path, index: usize) -> Result<PathBuf> {
+    let template = template.to_string_lossy();
+
+    if !template.contains("{stem}") && !template.contains("{index}") {
+        bail!(
+            "Output `{}` contains neither {{stem}} nor {{index}}",
+            template
+        );
+    }
+
+    let stem = input
+        .file_stem()
+        .ok_or_else(|| anyhow!("{} has no file name", input.display()))?;
+
+    Ok(PathBuf::from(
+        template
+            .replace("{stem}", &stem.to_string_lossy())
+            .replace("{index}", &format!("{:04}", index)),
+    ))
+}
+
+impl RunOptions {
+    /// Options with the output names for the `index`-th `input`.
+    fn expand(&self, input: &Path, index: usize) -> Result<Self> {
+        let expand = |path: &Option<PathBuf>| {
+            path.as_ref()
+                .map(|path| expand(path, input, index))
+                .transpose()
+        };
+
+        let mut opt = self.clone();
+        opt.json = expand(&self.json)?;
+        opt.render.svg = expand(&self.render.svg)?;
+        opt.render.hpgl = expand(&self.render.hpgl)?;
+        opt.render.gcode = expand(&self.render.gcode)?;
+        Ok(opt)
+    }
+}
+
+pub fn run(opt: Options) -> Result<()> {
+    let inputs = inputs(&opt.input)?;
+    let options = inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| opt.run.expand(input, index))
+        .collect::<Result<Vec<_>>>()?;
+
+    info!("Processing {} images", inputs.len());
+
+    if !opt.animate {
+        return inputs
+            .par_iter()
+            .zip(&options)
+            .try_for_each(|(input, opt)| crate::run(input, opt));
+    }
+
+    // Relaxing depends on the previous frame, connecting and writing the points does not.
+    let mut frames: Vec<Document> = Vec::new();
+
+    for (input, opt) in inputs.iter().zip(&options) {
+        info!("Relax {}", input.display());
+        let img = read_image(input, &opt.preprocess, opt.sample.black_paper())?;
+        // Without Voronoi iterations the previous points would never move towards the new frame.
+        let points = match frames.last() {
+            Some(previous) if opt.relax.voronoi_iterations > 0 => previous.clone(),
+            _ => sample(&img, &opt.sample)?,
+        };
+        frames.push(relax(points, &img, &opt.relax)?);
+    }
+
+    frames
+        .into_par_iter()
+        .zip(&options)
+        .try_for_each(|(document, opt)| finish(document, opt))
+}
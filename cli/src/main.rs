@@ -1,90 +1,783 @@
-use anyhow::Result;
-use clap::Parser;
+mod batch;
+
+use anyhow::{bail, Error, Result};
+use clap::{Parser, Subcommand};
 use image::io::Reader;
 use image::GenericImageView;
-use inkdrop::{tsp, voronoi, Point};
+use inkdrop::color::{self, HueRange, Palette, Pen, Separation};
+use inkdrop::document::{Channel, Document, Kind, Settings};
+use inkdrop::gcode::{Calibration, Placement};
+use inkdrop::hpgl::PageSize;
+use inkdrop::preprocess::{Adjustments, Geometry, Rotation};
+use inkdrop::screen::Screen;
+use inkdrop::{tsp, voronoi, Budget, Point};
 use log::info;
 use rayon::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Options {
-    #[arg(long, short)]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    #[arg(long, short)]
-    svg: Option<PathBuf>,
+    #[arg(long, short, required = true)]
+    input: Option<PathBuf>,
 
+    #[command(flatten)]
+    run: RunOptions,
+}
+
+/// Outputs and parameters of all stages.
+#[derive(clap::Args, Clone)]
+struct RunOptions {
+    /// document with the points or paths of all channels for `gcode-converter`, written in the
+    /// compact binary format if the file name ends with `.inkb`
     #[arg(long, short)]
     json: Option<PathBuf>,
 
+    #[arg(long)]
+    draw_points: bool,
+
+    #[command(flatten)]
+    preprocess: ImageOptions,
+
+    #[command(flatten)]
+    sample: SampleOptions,
+
+    #[command(flatten)]
+    relax: RelaxOptions,
+
+    #[command(flatten)]
+    tour: TourOptions,
+
+    #[command(flatten)]
+    render: RenderOptions,
+}
+
+// Stages of the conversion which read and write documents so that each of them can be rerun on
+// its own.
+#[derive(Subcommand)]
+enum Command {
+    /// Sample points from an image
+    Sample {
+        #[arg(long, short)]
+        input: PathBuf,
+
+        /// document with the sampled points
+        #[arg(long, short)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        preprocess: ImageOptions,
+
+        #[command(flatten)]
+        options: SampleOptions,
+    },
+
+    /// Move sampled points using the weighted Voronoi method
+    Relax {
+        /// document with sampled points
+        #[arg(long, short)]
+        input: PathBuf,
+
+        /// image the points were sampled from
+        #[arg(long)]
+        image: PathBuf,
+
+        #[arg(long, short)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        preprocess: ImageOptions,
+
+        #[command(flatten)]
+        options: RelaxOptions,
+    },
+
+    /// Connect the points of each channel to a tour
+    Tour {
+        #[arg(long, short)]
+        input: PathBuf,
+
+        #[arg(long, short)]
+        output: PathBuf,
+
+        #[command(flatten)]
+        options: TourOptions,
+    },
+
+    /// Draw the points or paths of a document
+    Render {
+        #[arg(long, short)]
+        input: PathBuf,
+
+        #[command(flatten)]
+        options: RenderOptions,
+    },
+
+    /// Run all stages for a directory of images or the frames of an animation
+    Batch(Box<batch::Options>),
+}
+
+/// Adjustments of the image applied before sampling and relaxing.
+#[derive(clap::Args, Clone)]
+struct ImageOptions {
+    /// part of the image to keep given as `left,top,width,height` in pixels
+    #[arg(long, value_delimiter = ',')]
+    crop: Option<Vec<u32>>,
+
+    /// clockwise rotation in degrees, 90, 180 or 270
+    #[arg(long)]
+    rotate: Option<Rotation>,
+
+    #[arg(long)]
+    flip_horizontal: bool,
+
+    #[arg(long)]
+    flip_vertical: bool,
+
+    /// width in pixels the image is scaled to, keeping its aspect ratio
+    #[arg(long)]
+    resize_width: Option<u32>,
+
+    /// height in pixels the image is scaled to, keeping its aspect ratio
+    #[arg(long)]
+    resize_height: Option<u32>,
+
+    /// replace the background connected to the border with white, optionally giving the
+    /// maximum difference of each channel to the background color
+    #[arg(long, num_args = 0..=1, default_missing_value = "32")]
+    remove_background: Option<u8>,
+
+    /// input value mapped to black, 0 to 255
+    #[arg(long, default_value = "0")]
+    black_point: u8,
+
+    /// input value mapped to white, 0 to 255
+    #[arg(long, default_value = "255")]
+    white_point: u8,
+
+    /// spread the values of the image evenly over the whole range
+    #[arg(long)]
+    equalize: bool,
+
+    /// value added to all channels, negative values darken the image
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    brightness: i32,
+
+    /// change of contrast in percent, negative values reduce it
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    contrast: f32,
+
+    /// standard deviation of a Gaussian blur in pixels
+    #[arg(long)]
+    blur: Option<f32>,
+
+    /// standard deviation of an unsharp mask in pixels
+    #[arg(long)]
+    unsharpen: Option<f32>,
+
+    /// minimum difference to the blurred image that is sharpened by `--unsharpen`
+    #[arg(long, default_value = "0")]
+    unsharpen_threshold: i32,
+
+    /// weight of the edge magnitude added to the darkness to place more points along contours,
+    /// e.g. 0.5
+    #[arg(long, default_value = "0")]
+    edges: f32,
+}
+
+#[derive(clap::Args, Clone)]
+struct SampleOptions {
     #[arg(long, short, default_value = "20000")]
     num_points: usize,
 
+    #[arg(long, default_value = "1")]
+    gamma: f32,
+
     #[arg(long)]
-    draw_points: bool,
+    cmyk: bool,
+
+    /// percentage of the grey component of the colored inks drawn with black instead
+    #[arg(long, default_value = "100", requires = "cmyk")]
+    black_generation: f32,
+
+    /// percentage of the black ink removed from the colored inks
+    #[arg(long, default_value = "100", requires = "cmyk")]
+    under_color_removal: f32,
+
+    /// maximum sum of all inks in percent, e.g. 300
+    #[arg(long, default_value = "400", requires = "cmyk")]
+    ink_limit: f32,
+
+    /// one channel for each pen given as `#rrggbb` or `name=#rrggbb`, separated by commas
+    #[arg(long, value_delimiter = ',', conflicts_with = "cmyk")]
+    palette: Option<Vec<Pen>>,
+
+    /// draw with light pens on black paper, placing points where the image is bright
+    #[arg(long, conflicts_with = "cmyk")]
+    black_paper: bool,
+
+    /// red, green and blue channels for gel pens on black paper
+    #[arg(long, conflicts_with_all = ["cmyk", "palette"])]
+    rgb: bool,
+
+    /// range of hues in degrees drawn with a spot color, e.g. `330-30` for reds, with all other
+    /// colors drawn in black
+    #[arg(long, conflicts_with_all = ["cmyk", "palette", "rgb", "black_paper"])]
+    spot_hue: Option<HueRange>,
+
+    /// points of each channel as counts or percentages of `--num-points`, e.g.
+    /// `30%,30%,10%,30%`, instead of dividing them by the densities of the channels
+    #[arg(long, value_delimiter = ',')]
+    channel_points: Option<Vec<ChannelPoints>>,
+
+    /// gamma of each channel instead of `--gamma`
+    #[arg(long, value_delimiter = ',')]
+    channel_gamma: Option<Vec<f32>>,
+}
 
+/// Number of points of a channel, either absolute or as a share of all points.
+#[derive(Copy, Clone)]
+enum ChannelPoints {
+    Count(usize),
+    Percent(f32),
+}
+
+impl FromStr for ChannelPoints {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_suffix('%') {
+            Some(percent) => Ok(Self::Percent(percent.trim().parse()?)),
+            None => Ok(Self::Count(s.trim().parse()?)),
+        }
+    }
+}
+
+impl ChannelPoints {
+    fn resolve(self, num_points: usize) -> usize {
+        match self {
+            Self::Count(count) => count,
+            Self::Percent(percent) => (num_points as f32 * percent / 100.0).round() as usize,
+        }
+    }
+}
+
+#[derive(clap::Args, Clone)]
+struct RelaxOptions {
     #[arg(long, default_value = "0")]
     voronoi_iterations: usize,
 
+    /// minimum distance in pixels between points of different channels, enforced after every
+    /// Voronoi iteration
+    #[arg(long)]
+    min_distance: Option<f64>,
+
+    /// halftone screen angle in degrees of each channel, e.g. `15,75,0,45` for CMYK
+    #[arg(long, value_delimiter = ',')]
+    screen_angles: Option<Vec<f64>>,
+
+    /// shift of the screen of each channel as a fraction of the screen spacing, e.g.
+    /// `0,0.25,0.5,0.75`
+    #[arg(long, value_delimiter = ',', requires = "screen_angles")]
+    screen_offsets: Option<Vec<f64>>,
+
+    /// distance in pixels between the nodes of the screens, half the average distance between
+    /// points if not given
+    #[arg(long, requires = "screen_angles")]
+    screen_spacing: Option<f64>,
+}
+
+#[derive(clap::Args, Clone)]
+struct TourOptions {
     #[arg(long, default_value = "0")]
     tsp_improvement: f64,
+}
 
-    #[arg(long, default_value = "1")]
-    gamma: f32,
+#[derive(clap::Args, Clone)]
+struct RenderOptions {
+    #[arg(long, short)]
+    svg: Option<PathBuf>,
 
+    /// HPGL file for pen plotters such as the HP 7475A
     #[arg(long)]
-    cmyk: bool,
+    hpgl: Option<PathBuf>,
+
+    /// page size of the HPGL output, `a4`, `a3`, `letter` or `tabloid`
+    #[arg(long, default_value = "a4")]
+    hpgl_page: PageSize,
+
+    /// pen carousel slot from 1 to 6 for each channel, consecutive slots if not given
+    #[arg(long, value_delimiter = ',')]
+    hpgl_pens: Option<Vec<u8>>,
+
+    /// calibration of the drawing machine as used by `gcode-converter`
+    #[arg(long, requires = "gcode")]
+    calibration: Option<PathBuf>,
+
+    /// directory receiving one gcode file per channel
+    #[arg(long, requires = "calibration")]
+    gcode: Option<PathBuf>,
 }
 
-fn main() -> Result<()> {
-    env_logger::init();
+/// Read and adjust the image, filling transparent parts and the background with the color of the
+/// paper.
+fn read_image(path: &Path, opt: &ImageOptions, black_paper: bool) -> Result<image::DynamicImage> {
+    let crop = match opt.crop.as_deref() {
+        Some(&[x, y, width, height]) => Some((x, y, width, height)),
+        Some(_) => bail!("Crop rectangle must be given as `left,top,width,height`"),
+        None => None,
+    };
 
-    let opt = Options::parse();
-    let img = Reader::open(&opt.input)?.decode()?;
+    let geometry = Geometry {
+        crop,
+        rotate: opt.rotate,
+        flip_horizontal: opt.flip_horizontal,
+        flip_vertical: opt.flip_vertical,
+        width: opt.resize_width,
+        height: opt.resize_height,
+    };
+    let img = geometry.apply(Reader::open(path)?.decode()?)?;
+
+    let adjustments = Adjustments {
+        paper: if black_paper {
+            [0, 0, 0]
+        } else {
+            [255, 255, 255]
+        },
+        remove_background: opt.remove_background,
+        black_point: opt.black_point,
+        white_point: opt.white_point,
+        equalize: opt.equalize,
+        brightness: opt.brightness,
+        contrast: opt.contrast,
+        blur: opt.blur,
+        unsharpen: opt.unsharpen.map(|sigma| (sigma, opt.unsharpen_threshold)),
+        edges: opt.edges,
+    };
+
+    Ok(adjustments.apply(&img))
+}
+
+/// Points of each channel regardless of how they are connected.
+fn points(document: &Document) -> Vec<Vec<Point>> {
+    document
+        .channels
+        .iter()
+        .map(|channel| channel.strokes.concat())
+        .collect()
+}
+
+impl SampleOptions {
+    fn black_paper(&self) -> bool {
+        self.black_paper || self.rgb
+    }
+}
+
+fn sample(img: &image::DynamicImage, opt: &SampleOptions) -> Result<Document> {
     let (width, height) = img.dimensions();
+    let black_paper = opt.black_paper();
 
     info!("Sample points");
-    let mut point_sets = inkdrop::sample_points(&img, opt.num_points, opt.gamma, opt.cmyk);
 
-    if opt.voronoi_iterations > 0 {
-        info!("Move points");
+    let channel = |name: &str, color: String| Channel {
+        name: name.to_string(),
+        color,
+        strokes: Vec::new(),
+    };
 
-        for _ in 0..opt.voronoi_iterations {
-            point_sets = point_sets
-                .into_iter()
-                .map(|ps| voronoi::move_points(ps, &img))
-                .collect::<Result<Vec<_>>>()?;
+    let palette = opt.palette.as_ref().map(|pens| {
+        if black_paper {
+            Palette::on_black_paper(pens.clone())
+        } else {
+            Palette::new(pens.clone())
         }
+    });
+
+    let separation = Separation {
+        black_generation: opt.black_generation / 100.0,
+        under_color_removal: opt.under_color_removal / 100.0,
+        ink_limit: opt.ink_limit / 100.0,
+    };
+
+    // Names and colors of the channels and the density of each of them for a color.
+    type Densities<'a> = Box<dyn FnMut(u8, u8, u8) -> Vec<f32> + 'a>;
+
+    let (mut channels, densities): (Vec<Channel>, Densities) = if let Some(palette) = &palette {
+        (
+            palette
+                .pens()
+                .iter()
+                .map(|pen| channel(&pen.name, pen.hex()))
+                .collect(),
+            Box::new(palette.cached_densities()),
+        )
+    } else if opt.rgb {
+        (
+            ["red", "green", "blue"]
+                .iter()
+                .map(|color| channel(color, color.to_string()))
+                .collect(),
+            Box::new(|r, g, b| {
+                let (r, g, b) = color::to_rgb(r, g, b);
+                vec![r, g, b]
+            }),
+        )
+    } else if let Some(range) = &opt.spot_hue {
+        let spot = Pen {
+            name: "spot".to_string(),
+            rgb: color::from_hue(range.center()),
+        };
+
+        (
+            vec![
+                channel(&spot.name, spot.hex()),
+                channel("black", "black".to_string()),
+            ],
+            Box::new(move |r, g, b| {
+                let (spot, black) = range.separate(r, g, b);
+                vec![spot, black]
+            }),
+        )
+    } else if black_paper {
+        (
+            vec![channel("white", "white".to_string())],
+            Box::new(|r, g, b| vec![1.0 - color::to_black(r, g, b)]),
+        )
+    } else {
+        (
+            (0..4)
+                .map(|i| Channel::numbered(i, 4, Vec::new()))
+                .collect(),
+            if opt.cmyk {
+                Box::new(move |r, g, b| {
+                    let (c, m, y, k) = separation.separate(r, g, b);
+                    vec![c, m, y, k]
+                })
+            } else {
+                Box::new(|r, g, b| vec![0.0, 0.0, 0.0, color::to_black(r, g, b)])
+            },
+        )
+    };
+
+    let gammas = opt.channel_gamma.clone().unwrap_or_else(|| vec![opt.gamma]);
+
+    if opt.channel_gamma.is_some() && gammas.len() != channels.len() {
+        bail!(
+            "Channel gamma must be given for each of the {} channels",
+            channels.len()
+        );
     }
 
-    if opt.draw_points {
-        if let Some(path) = opt.svg {
-            inkdrop::svg::write_points(&path, &point_sets, width, height)?;
+    let budgets = match &opt.channel_points {
+        Some(points) => {
+            if points.len() != channels.len() {
+                bail!(
+                    "Channel points must be given for each of the {} channels",
+                    channels.len()
+                );
+            }
+
+            Some(
+                points
+                    .iter()
+                    .enumerate()
+                    .map(|(index, points)| Budget {
+                        num_points: points.resolve(opt.num_points),
+                        gamma: *gammas.get(index).unwrap_or(&gammas[0]),
+                    })
+                    .collect::<Vec<_>>(),
+            )
         }
-    } else {
-        info!("Make NN tours");
-
-        let tours: Vec<Vec<Point>> = point_sets
-            .into_par_iter()
-            .map(|points| {
-                if opt.tsp_improvement != 0.0 {
-                    tsp::optimize(tsp::make_nn_tour(points), opt.tsp_improvement)
-                } else {
-                    tsp::make_nn_tour(points)
-                }
-            })
+        None => None,
+    };
+
+    let point_sets = match &budgets {
+        Some(budgets) => inkdrop::sample_budgets(img, budgets, densities)?,
+        None => inkdrop::sample_channels(img, opt.num_points, &gammas, densities),
+    };
+
+    for (channel, points) in channels.iter_mut().zip(point_sets) {
+        channel.strokes = vec![points];
+    }
+
+    let settings = Settings {
+        num_points: Some(opt.num_points),
+        gamma: Some(opt.gamma),
+        cmyk: Some(opt.cmyk),
+        black_paper: Some(black_paper),
+        channel_points: budgets
+            .as_ref()
+            .map(|budgets| budgets.iter().map(|b| b.num_points).collect()),
+        channel_gamma: opt.channel_gamma.clone(),
+        voronoi_iterations: Some(0),
+        min_distance: None,
+        screens: None,
+        tsp_improvement: None,
+    };
+
+    Ok(Document::with_channels(
+        width,
+        height,
+        Kind::Points,
+        settings,
+        channels,
+    ))
+}
+
+fn relax(document: Document, img: &image::DynamicImage, opt: &RelaxOptions) -> Result<Document> {
+    for (name, value) in [
+        ("minimum distance", opt.min_distance),
+        ("screen spacing", opt.screen_spacing),
+    ] {
+        if let Some(value) = value.filter(|v| !(v.is_finite() && *v > 0.0)) {
+            bail!("The {} must be positive, not {}", name, value);
+        }
+    }
+
+    let mut screen_values = opt
+        .screen_angles
+        .iter()
+        .chain(&opt.screen_offsets)
+        .flatten();
+
+    if let Some(value) = screen_values.find(|v| !v.is_finite()) {
+        bail!("Screen angles and offsets must be finite, not {}", value);
+    }
+
+    if opt.voronoi_iterations == 0 && opt.screen_angles.is_none() {
+        return Ok(document);
+    }
+
+    let (width, height) = img.dimensions();
+
+    if document.width.is_some_and(|w| w != width) || document.height.is_some_and(|h| h != height) {
+        bail!(
+            "Points were not sampled from an image of {}x{}",
+            width,
+            height
+        );
+    }
+
+    // Points on black paper gather where the image is bright.
+    let inverted = (document.settings.black_paper == Some(true)).then(|| {
+        let mut img = img.clone();
+        img.invert();
+        img
+    });
+    let img = inverted.as_ref().unwrap_or(img);
+
+    info!("Move points");
+    let mut point_sets = points(&document);
+
+    for _ in 0..opt.voronoi_iterations {
+        point_sets = point_sets
+            .into_iter()
+            .map(|ps| voronoi::move_points(ps, img))
+            .collect::<Result<Vec<_>>>()?;
+
+        if let Some(min_distance) = opt.min_distance {
+            voronoi::keep_apart(&mut point_sets, min_distance, width, height);
+        }
+    }
+
+    let screens = screens(&point_sets, width, height, opt)?;
+
+    if let Some(screens) = &screens {
+        info!("Apply screens");
+
+        point_sets = point_sets
+            .iter()
+            .zip(screens)
+            .map(|(points, screen)| screen.apply(points))
             .collect();
+    }
+
+    let mut document = document;
+    let settings = &mut document.settings;
+    settings.voronoi_iterations =
+        Some(settings.voronoi_iterations.unwrap_or(0) + opt.voronoi_iterations);
+
+    if opt.voronoi_iterations > 0 && opt.min_distance.is_some() {
+        settings.min_distance = opt.min_distance;
+    }
+
+    if let Some(screens) = screens {
+        settings.screens = Some(screens);
+    }
+    document.width = Some(width);
+    document.height = Some(height);
+
+    let kind = document.kind;
+    Ok(document.with_point_sets(kind, point_sets))
+}
+
+/// Screen of each channel if screen angles are given.
+fn screens(
+    point_sets: &[Vec<Point>],
+    width: u32,
+    height: u32,
+    opt: &RelaxOptions,
+) -> Result<Option<Vec<Screen>>> {
+    let angles = match &opt.screen_angles {
+        Some(angles) => angles,
+        None => return Ok(None),
+    };
+    let offsets = opt.screen_offsets.clone().unwrap_or_default();
+
+    if angles.len() != point_sets.len() || (!offsets.is_empty() && offsets.len() != angles.len()) {
+        bail!(
+            "Screen angles and offsets must be given for each of the {} channels",
+            point_sets.len()
+        );
+    }
+
+    let num_points = point_sets.iter().map(Vec::len).sum::<usize>().max(1);
+    let spacing = opt
+        .screen_spacing
+        .unwrap_or_else(|| 0.5 * (width as f64 * height as f64 / num_points as f64).sqrt());
+
+    Ok(Some(
+        angles
+            .iter()
+            .enumerate()
+            .map(|(index, angle)| Screen {
+                angle: *angle,
+                offset: offsets.get(index).copied().unwrap_or(0.0),
+                spacing,
+            })
+            .collect(),
+    ))
+}
+
+fn tour(document: Document, opt: &TourOptions) -> Document {
+    info!("Make NN tours");
+
+    let tours: Vec<Vec<Point>> = points(&document)
+        .into_par_iter()
+        .map(|points| {
+            if opt.tsp_improvement != 0.0 {
+                tsp::optimize(tsp::make_nn_tour(points), opt.tsp_improvement)
+            } else {
+                tsp::make_nn_tour(points)
+            }
+        })
+        .collect();
+
+    let mut document = document;
+    document.settings.tsp_improvement = Some(opt.tsp_improvement);
+    document.with_point_sets(Kind::Paths, tours)
+}
+
+fn dimensions(document: &Document) -> Result<(u32, u32)> {
+    match (document.width, document.height) {
+        (Some(width), Some(height)) => Ok((width, height)),
+        _ => bail!("Document does not record the image dimensions"),
+    }
+}
 
-        if let Some(path) = opt.svg {
-            inkdrop::svg::write_path(&path, &tours, width, height)?;
+/// Draw each channel as a single path or as points and convert them to gcode.
+fn render(document: &Document, opt: &RenderOptions) -> Result<()> {
+    let (width, height) = dimensions(document)?;
+    let point_sets = points(document);
+    let colors = document.colors();
+    let paper = (document.settings.black_paper == Some(true)).then_some("black");
+    let pens = opt.hpgl_pens.as_deref();
+
+    match document.kind {
+        Kind::Points => {
+            if let Some(path) = &opt.svg {
+                inkdrop::svg::write_points(path, &point_sets, &colors, paper, width, height)?;
+            }
+            if let Some(path) = &opt.hpgl {
+                inkdrop::hpgl::write_points(path, &point_sets, width, height, opt.hpgl_page, pens)?;
+            }
+        }
+        Kind::Paths => {
+            if let Some(path) = &opt.svg {
+                inkdrop::svg::write_path(path, &point_sets, &colors, paper, width, height)?;
+            }
+            if let Some(path) = &opt.hpgl {
+                inkdrop::hpgl::write_path(path, &point_sets, width, height, opt.hpgl_page, pens)?;
+            }
         }
-        if let Some(path) = opt.json {
-            // serialize channels
-            let fh = std::fs::File::create(path)?;
-            serde_json::to_writer_pretty(&fh, &tours)?;
+    }
+
+    if let Some(dir) = &opt.gcode {
+        let calib = Calibration::read(opt.calibration.as_ref().unwrap())?;
+        let channels = calib.layout(&document.strokes(), &Placement::default(), false)?;
+        std::fs::create_dir_all(dir)?;
+
+        for (index, strokes) in channels.iter().enumerate() {
+            let gcode = calib.gcode(&calib.transform_coordinates(strokes));
+            std::fs::write(dir.join(format!("channel_{index:03}.gcode")), gcode)?;
         }
     }
 
     Ok(())
 }
+
+/// Connect the relaxed points unless only points are drawn and write all outputs.
+fn finish(document: Document, opt: &RunOptions) -> Result<()> {
+    let document = if opt.draw_points {
+        document
+    } else {
+        tour(document, &opt.tour)
+    };
+
+    render(&document, &opt.render)?;
+
+    if let Some(path) = &opt.json {
+        document.write(path)?;
+    }
+
+    Ok(())
+}
+
+/// Run all stages at once.
+fn run(input: &Path, opt: &RunOptions) -> Result<()> {
+    let img = read_image(input, &opt.preprocess, opt.sample.black_paper())?;
+    finish(relax(sample(&img, &opt.sample)?, &img, &opt.relax)?, opt)
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let opt = Options::parse();
+
+    match opt.command {
+        None => run(opt.input.as_ref().unwrap(), &opt.run),
+        Some(Command::Sample {
+            input,
+            output,
+            preprocess,
+            options,
+        }) => sample(
+            &read_image(&input, &preprocess, options.black_paper())?,
+            &options,
+        )?
+        .write(&output),
+        Some(Command::Relax {
+            input,
+            image,
+            output,
+            preprocess,
+            options,
+        }) => {
+            let document = Document::read(&input)?;
+            let black_paper = document.settings.black_paper == Some(true);
+            let img = read_image(&image, &preprocess, black_paper)?;
+            relax(document, &img, &options)?.write(&output)
+        }
+        Some(Command::Tour {
+            input,
+            output,
+            options,
+        }) => tour(Document::read(&input)?, &options).write(&output),
+        Some(Command::Render { input, options }) => render(&Document::read(&input)?, &options),
+        Some(Command::Batch(options)) => batch::run(*options),
+    }
+}
@@ -1,19 +1,41 @@
-use anyhow::Result;
-use clap::Parser;
-use inkdrop::gcode::{Calibration, Channel, Channels};
-use serde::Deserialize;
+mod preview;
+mod send;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use inkdrop::document::Document;
+use inkdrop::gcode::resume::{resume_strokes, Start};
+use inkdrop::gcode::{Anchor, Calibration, Dialect, Layer, Placement};
+use inkdrop::Point;
 use std::io::Write;
 use std::path::PathBuf;
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum ChannelOrChannels {
-    Channel(Channel),
-    Channels(Channels),
-}
-
 #[derive(Parser)]
 pub struct Options {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a point document into one gcode file per channel
+    Convert(ConvertOptions),
+
+    /// Render gcode back into an SVG or PNG image and report statistics
+    Preview(preview::Options),
+
+    /// Stream gcode to a GRBL compatible controller
+    Send(send::Options),
+
+    /// Print the built-in template of a dialect as a starting point for custom templates
+    Template {
+        /// `generic`, `grbl`, `marlin` or `reprap`
+        dialect: Dialect,
+    },
+}
+
+#[derive(clap::Args)]
+struct ConvertOptions {
     #[arg(long, short)]
     input: PathBuf,
 
@@ -22,35 +44,156 @@ pub struct Options {
 
     #[arg(long, short)]
     calibration: PathBuf,
-}
 
-fn main() -> Result<()> {
-    env_logger::init();
+    /// cut strokes at the border of the drawing area instead of failing
+    #[arg(long)]
+    clip: bool,
+
+    /// fixed scale in millimeters per pixel instead of filling the drawing area
+    #[arg(long)]
+    scale: Option<f64>,
+
+    /// point of the drawing aligned with the same point of the drawing area, e.g. `top-left`
+    #[arg(long, default_value = "center")]
+    anchor: Anchor,
+
+    /// horizontal offset in millimeters applied after anchoring
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    offset_x: f64,
+
+    /// vertical offset in millimeters applied after anchoring
+    #[arg(long, default_value = "0", allow_negative_numbers = true)]
+    offset_y: f64,
+
+    /// skip all channels before this one when resuming a plot
+    #[arg(long, default_value = "0")]
+    start_channel: usize,
+
+    /// first stroke of the start channel to draw
+    #[arg(long, conflicts_with = "start_percent")]
+    start_stroke: Option<usize>,
+
+    /// percentage of the drawn length of the start channel to skip
+    #[arg(long)]
+    start_percent: Option<f64>,
+
+    /// write all channels into the single file given by `--output`, pausing for pen changes
+    #[arg(long)]
+    combined: bool,
 
-    let opt = Options::parse();
+    /// template used instead of the one of the calibrated dialect
+    #[arg(long, short)]
+    template: Option<PathBuf>,
+}
 
-    let fh_in = std::fs::File::open(opt.input)?;
-    let fh_calib = std::fs::File::open(opt.calibration)?;
+impl ConvertOptions {
+    fn start(&self) -> Option<Start> {
+        self.start_stroke
+            .map(Start::Index)
+            .or(self.start_percent.map(Start::Percent))
+    }
+}
 
-    let calib: Calibration = serde_json::from_reader(fh_calib)?;
-    let channels: ChannelOrChannels = serde_json::from_reader(fh_in)?;
+fn convert(opt: ConvertOptions) -> Result<()> {
+    let document = Document::read(&opt.input)?;
+    let calib = Calibration::read(&opt.calibration)?;
+    let template = opt
+        .template
+        .as_ref()
+        .map(std::fs::read_to_string)
+        .transpose()?;
+    let channels = document.strokes();
 
-    let channels = match channels {
-        ChannelOrChannels::Channel(c) => vec![c],
-        ChannelOrChannels::Channels(c) => c,
+    let placement = Placement {
+        scale: opt.scale,
+        anchor: opt.anchor,
+        offset: Point::new(opt.offset_x, opt.offset_y),
     };
 
-    let translated = calib.translate_origin(&channels);
-    let transformed = calib.transform_coordinates(&translated);
+    // All channels are placed with the same transform to keep them registered.
+    let translated = calib.layout(&channels, &placement, opt.clip)?;
+
+    // Resuming past the end would silently write nothing.
+    if opt.start_channel >= translated.len() {
+        bail!(
+            "Start channel {} is out of range, the document has {} channels",
+            opt.start_channel,
+            translated.len()
+        );
+    }
 
-    std::fs::create_dir_all(&opt.output)?;
+    if let Some(stroke) = opt.start_stroke {
+        let count = translated[opt.start_channel].len();
 
-    for (index, channel) in transformed.iter().enumerate() {
-        let filename = opt.output.join(format!("channel_{index:03}.gcode"));
-        let mut fh = std::fs::File::create(&filename)?;
-        let gcode = calib.gcode(channel);
+        if stroke >= count {
+            bail!(
+                "Start stroke {} is out of range, channel {} has {} strokes",
+                stroke,
+                opt.start_channel,
+                count
+            );
+        }
+    }
+
+    let mut layers = Vec::new();
+
+    for (index, mut strokes) in translated.into_iter().enumerate().skip(opt.start_channel) {
+        if let (true, Some(start)) = (index == opt.start_channel, opt.start()) {
+            strokes = resume_strokes(&strokes, start);
+        }
+
+        let name = document.channels[index].name.clone();
+        layers.push((index, name, calib.transform_coordinates(&strokes)));
+    }
+
+    if opt.combined {
+        let layers = layers
+            .iter()
+            .filter(|(_, _, strokes)| strokes.iter().any(|s| !s.is_empty()))
+            .map(|(_, name, strokes)| Layer { name, strokes })
+            .collect::<Vec<_>>();
+
+        let gcode = match &template {
+            Some(template) => calib.render(template, &layers, true)?,
+            None => calib.combined_gcode(&layers),
+        };
+
+        let mut fh = std::fs::File::create(&opt.output)?;
         fh.write_all(gcode.as_bytes())?;
+    } else {
+        std::fs::create_dir_all(&opt.output)?;
+
+        for (index, name, strokes) in layers {
+            let filename = opt.output.join(format!("channel_{index:03}.gcode"));
+            let mut fh = std::fs::File::create(&filename)?;
+            let gcode = match &template {
+                Some(template) => calib.render(
+                    template,
+                    &[Layer {
+                        name: &name,
+                        strokes: &strokes,
+                    }],
+                    false,
+                )?,
+                None => calib.gcode(&strokes),
+            };
+            fh.write_all(gcode.as_bytes())?;
+        }
     }
 
     Ok(())
 }
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    match Options::parse().command {
+        Command::Convert(opt) => convert(opt),
+        Command::Preview(opt) => preview::run(opt),
+        Command::Send(opt) => send::run(opt),
+        Command::Template { dialect } => {
+            print!("{}", dialect.template());
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, bail, Result};
+use inkdrop::gcode::preview::{Feedrates, Preview};
+use inkdrop::gcode::Calibration;
+use inkdrop::Point;
+use std::path::PathBuf;
+
+#[derive(clap::Args)]
+pub struct Options {
+    /// gcode file to preview
+    #[arg(long, short)]
+    input: PathBuf,
+
+    /// SVG or PNG image to write, chosen by file extension
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+
+    #[arg(long, short)]
+    calibration: PathBuf,
+
+    /// feed rate of drawing moves in mm/min unless given in the gcode
+    #[arg(long, default_value = "3000")]
+    draw_feed: f64,
+
+    /// feed rate of travel moves in mm/min
+    #[arg(long, default_value = "6000")]
+    travel_feed: f64,
+
+    /// pixels per millimeter of PNG output
+    #[arg(long, default_value = "4")]
+    resolution: f64,
+}
+
+fn draw_line(img: &mut image::GrayImage, from: Point, to: Point) {
+    let steps = from.distance(&to).ceil().max(1.0) as usize;
+
+    for step in 0..=steps {
+        let p = from + (to - from) * (step as f64 / steps as f64);
+
+        if p.x >= 0.0 && p.y >= 0.0 && (p.x as u32) < img.width() && (p.y as u32) < img.height() {
+            img.put_pixel(p.x as u32, p.y as u32, image::Luma([0]));
+        }
+    }
+}
+
+fn write_png(path: &PathBuf, strokes: &[Vec<Point>], width: u32, height: u32) -> Result<()> {
+    let mut img = image::GrayImage::from_pixel(width, height, image::Luma([255]));
+
+    for stroke in strokes {
+        for segment in stroke.windows(2) {
+            draw_line(&mut img, segment[0], segment[1]);
+        }
+    }
+
+    Ok(img.save(path)?)
+}
+
+pub fn run(opt: Options) -> Result<()> {
+    for (name, feed) in [("draw", opt.draw_feed), ("travel", opt.travel_feed)] {
+        if !(feed.is_finite() && feed > 0.0) {
+            bail!("The {} feed rate must be positive, not {}", name, feed);
+        }
+    }
+
+    let calib = Calibration::read(&opt.calibration)?;
+    let gcode = std::fs::read_to_string(&opt.input)?;
+    let feedrates = Feedrates {
+        draw: opt.draw_feed,
+        travel: opt.travel_feed,
+    };
+
+    let preview = Preview::new(&calib, &gcode, feedrates);
+    let size = preview.max - preview.min;
+
+    println!(
+        "bounds: ({:.1}, {:.1}) to ({:.1}, {:.1}), {:.1} x {:.1} mm",
+        preview.min.x, preview.min.y, preview.max.x, preview.max.y, size.x, size.y
+    );
+    println!("draw distance: {:.1} mm", preview.draw_distance);
+    println!("travel distance: {:.1} mm", preview.travel_distance);
+    println!(
+        "estimated time: {}:{:02}:{:02}",
+        preview.duration.as_secs() / 3600,
+        preview.duration.as_secs() / 60 % 60,
+        preview.duration.as_secs() % 60
+    );
+
+    if let Some(path) = opt.output {
+        let (svg, scale) = match path.extension().and_then(|e| e.to_str()) {
+            Some("svg") => (true, 1.0),
+            Some("png") => (false, opt.resolution),
+            _ => return Err(anyhow!("Output must be an .svg or .png file")),
+        };
+
+        let strokes = preview
+            .strokes
+            .iter()
+            .map(|s| s.iter().map(|p| (*p - preview.min) * scale).collect())
+            .collect::<Vec<_>>();
+
+        let width = (size.x * scale).ceil() as u32 + 1;
+        let height = (size.y * scale).ceil() as u32 + 1;
+
+        if svg {
+            inkdrop::svg::write_strokes(&path, &strokes, "black", width, height)?;
+        } else {
+            write_png(&path, &strokes, width, height)?;
+        }
+    }
+
+    Ok(())
+}
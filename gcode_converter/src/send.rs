@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+use inkdrop::gcode::resume::{resume_program, Start};
+use inkdrop::gcode::sender::{program_lines, Event, Protocol, Sender};
+use inkdrop::gcode::Calibration;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufWriter};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The checkpoint is written after this many acknowledged lines ...
+const CHECKPOINT_LINES: usize = 100;
+
+/// ... or this much time, whichever comes first.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Progress of a plot written while streaming.
+#[derive(Deserialize, Serialize)]
+struct Checkpoint {
+    input: PathBuf,
+
+    /// number of program lines acknowledged by the controller
+    line: usize,
+
+    /// total number of program lines
+    total: usize,
+}
+
+#[derive(clap::Args)]
+pub struct Options {
+    /// gcode file to stream
+    #[arg(long, short)]
+    input: PathBuf,
+
+    /// serial port or PTY of the controller, e.g. /dev/ttyUSB0
+    #[arg(long, short)]
+    port: String,
+
+    #[arg(long, default_value = "115200")]
+    baud_rate: u32,
+
+    /// `character-counting` or `send-response`
+    #[arg(long, default_value = "character-counting")]
+    protocol: Protocol,
+
+    /// receive buffer size of the controller used for character counting
+    #[arg(long, default_value = "128")]
+    rx_buffer_size: usize,
+
+    /// calibration providing the pen commands used when resuming
+    #[arg(long, short)]
+    calibration: Option<PathBuf>,
+
+    /// first program line to send, not counting comments and empty lines
+    #[arg(long, conflicts_with_all = ["start_percent", "resume"])]
+    start_line: Option<usize>,
+
+    /// percentage of program lines to skip
+    #[arg(long, conflicts_with = "resume")]
+    start_percent: Option<f64>,
+
+    /// file recording the progress of the plot
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// continue at the line recorded in the checkpoint file
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+}
+
+/// Replace the checkpoint atomically, so that a crash while writing leaves the previous one.
+fn write_checkpoint(path: &PathBuf, checkpoint: &Checkpoint) -> Result<()> {
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+
+    let mut fh = BufWriter::new(std::fs::File::create(&temporary)?);
+    serde_json::to_writer(&mut fh, checkpoint)?;
+    fh.into_inner()?.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+impl Options {
+    fn start(&self) -> Result<Option<Start>> {
+        if self.resume {
+            let path = self.checkpoint.as_ref().unwrap();
+            let checkpoint: Checkpoint = serde_json::from_reader(std::fs::File::open(path)?)?;
+
+            if checkpoint.input != self.input {
+                return Err(anyhow!(
+                    "Checkpoint was recorded for {}",
+                    checkpoint.input.display()
+                ));
+            }
+
+            return Ok(Some(Start::Index(checkpoint.line)));
+        }
+
+        Ok(self
+            .start_line
+            .map(Start::Index)
+            .or(self.start_percent.map(Start::Percent)))
+    }
+}
+
+pub fn run(opt: Options) -> Result<()> {
+    let gcode = std::fs::read_to_string(&opt.input)?;
+    let lines = program_lines(&gcode);
+
+    let (gcode, start, preamble) = match opt.start()? {
+        Some(start) => {
+            let start = start.line(lines.len());
+            let calib = opt
+                .calibration
+                .as_ref()
+                .map(|path| Calibration::read(path))
+                .transpose()?;
+            let pen_up = calib.as_ref().and_then(|c| c.pen_up());
+            let pen_down = calib.as_ref().and_then(|c| c.pen_down());
+            let resumed = resume_program(&lines, start, pen_up, pen_down);
+            let preamble = resumed.len() - (lines.len() - start);
+            info!("Resuming at line {} of {}", start, lines.len());
+            (resumed.join("\n"), start, preamble)
+        }
+        None => (gcode.clone(), 0, 0),
+    };
+
+    let mut port = serialport::new(&opt.port, opt.baud_rate)
+        .timeout(Duration::from_millis(100))
+        .open()?;
+
+    // Wake up GRBL and drop its startup message.
+    port.write_all(b"\r\n\r\n")?;
+    std::thread::sleep(Duration::from_secs(2));
+    port.clear(serialport::ClearBuffer::Input)?;
+
+    let mut sender = Sender::new(port, opt.protocol).with_buffer_size(opt.rx_buffer_size);
+    let control = sender.control();
+
+    println!("Enter `p` to pause, `r` to resume and `a` to abort");
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            match line.trim() {
+                "p" => control.pause(),
+                "r" => control.resume(),
+                "a" => control.abort(),
+                _ => {}
+            }
+        }
+    });
+
+    let mut checkpoint = Checkpoint {
+        input: opt.input.clone(),
+        line: start,
+        total: lines.len(),
+    };
+    let mut written = (start, Instant::now());
+
+    let save = |checkpoint: &Checkpoint| {
+        if let Some(path) = &opt.checkpoint {
+            if let Err(err) = write_checkpoint(path, checkpoint) {
+                warn!("Failed to write checkpoint: {}", err);
+            }
+        }
+    };
+
+    let result = sender.send(&gcode, |event| match event {
+        Event::Acknowledged { line, .. } => {
+            // Lines of the resume preamble do not count as progress.
+            checkpoint.line = start + line.saturating_sub(preamble);
+
+            if checkpoint.line >= written.0 + CHECKPOINT_LINES
+                || written.1.elapsed() >= CHECKPOINT_INTERVAL
+                || checkpoint.line == checkpoint.total
+            {
+                save(&checkpoint);
+                written = (checkpoint.line, Instant::now());
+            }
+
+            if checkpoint.line.is_multiple_of(100) || checkpoint.line == checkpoint.total {
+                info!("{} of {} lines done", checkpoint.line, checkpoint.total);
+            }
+        }
+        Event::ProgramPause(message) => {
+            save(&checkpoint);
+            written = (checkpoint.line, Instant::now());
+            println!("Program paused at `{}`, enter `r` to resume", message);
+        }
+    });
+
+    // Record the last acknowledged line even if streaming was aborted or failed.
+    save(&checkpoint);
+    result?;
+
+    info!("Done");
+
+    Ok(())
+}
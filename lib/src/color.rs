@@ -1,3 +1,7 @@
+use anyhow::{anyhow, Error, Result};
+use std::collections::HashMap;
+use std::str::FromStr;
+
 pub fn to_cmyk(r: u8, g: u8, b: u8) -> (f32, f32, f32, f32) {
     let max = r.max(g.max(b)) as f32 / 255.0;
     let r = r as f32 / 255.0;
@@ -26,3 +30,399 @@ pub fn to_black(r: u8, g: u8, b: u8) -> f32 {
 pub fn invert(c: (f32, f32, f32, f32)) -> Vec<f32> {
     vec![1.0 - c.0, 1.0 - c.1, 1.0 - c.2, 1.0 - c.3]
 }
+
+/// Hue, saturation and value of a color, with the hue in degrees and the others between 0 and 1.
+pub fn to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let chroma = max - r.min(g).min(b);
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+
+    let saturation = if max == 0.0 { 0.0 } else { chroma / max };
+
+    (hue, saturation, max)
+}
+
+/// Fully saturated color of `hue` in degrees.
+pub fn from_hue(hue: f32) -> [u8; 3] {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    [r, g, b].map(|v: f32| (v * 255.0).round() as u8)
+}
+
+/// Densities of red, green and blue pens drawn on black paper, where pens add light instead of
+/// absorbing it.
+pub fn to_rgb(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+}
+
+/// Range of hues in degrees from `start` to `end`, which wraps around, e.g. 330 to 30 for
+/// reds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HueRange {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl HueRange {
+    pub fn contains(&self, hue: f32) -> bool {
+        if self.start <= self.end {
+            hue >= self.start && hue <= self.end
+        } else {
+            hue >= self.start || hue <= self.end
+        }
+    }
+
+    /// Hue in the middle of the range.
+    pub fn center(&self) -> f32 {
+        let width = (self.end - self.start).rem_euclid(360.0);
+        (self.start + 0.5 * width).rem_euclid(360.0)
+    }
+
+    /// Densities of a spot color pen covering the colors of this range, proportional to their
+    /// chroma, and of a black pen for the darkness of all other colors.
+    pub fn separate(&self, r: u8, g: u8, b: u8) -> (f32, f32) {
+        let (hue, saturation, value) = to_hsv(r, g, b);
+
+        if saturation > 0.0 && self.contains(hue) {
+            (saturation * value, 0.0)
+        } else {
+            (0.0, to_black(r, g, b))
+        }
+    }
+}
+
+impl FromStr for HueRange {
+    type Err = Error;
+
+    /// Parse `start-end` in degrees.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| -> Result<f32> {
+            let v = v.trim().parse::<f32>()?;
+
+            if !(0.0..=360.0).contains(&v) {
+                return Err(anyhow!("Hue {} is not between 0 and 360 degrees", v));
+            }
+
+            Ok(v)
+        };
+
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid hue range `{}`, use start-end", s))?;
+
+        Ok(HueRange {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+}
+
+/// Separation of RGB colors into the inks of a CMYK print.
+///
+/// The default replaces the complete grey component with black, which is the same as
+/// [`to_cmyk`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Separation {
+    /// fraction of the grey component of the colored inks printed with black instead (GCR)
+    pub black_generation: f32,
+
+    /// fraction of the black ink that is removed from the colored inks (UCR)
+    pub under_color_removal: f32,
+
+    /// maximum sum of all inks, e.g. 3.0 for 300 %, reached by reducing the colored inks
+    pub ink_limit: f32,
+}
+
+impl Default for Separation {
+    fn default() -> Self {
+        Self {
+            black_generation: 1.0,
+            under_color_removal: 1.0,
+            ink_limit: 4.0,
+        }
+    }
+}
+
+impl Separation {
+    /// Amount of cyan, magenta, yellow and black ink needed for `r`, `g` and `b`.
+    pub fn separate(&self, r: u8, g: u8, b: u8) -> (f32, f32, f32, f32) {
+        let inks = [r, g, b].map(|v| 1.0 - v as f32 / 255.0);
+        let grey = inks.iter().copied().fold(1.0, f32::min);
+        let black = self.black_generation * grey;
+
+        // Points of different channels land independently, so black covers its fraction of
+        // the colored inks as well.
+        let mut colors = inks.map(|ink| {
+            let removed = if black < 1.0 {
+                (ink - black) / (1.0 - black)
+            } else {
+                0.0
+            };
+
+            ink + self.under_color_removal * (removed - ink)
+        });
+
+        let sum = colors.iter().sum::<f32>();
+        let available = (self.ink_limit - black).max(0.0);
+
+        if sum > available {
+            colors = colors.map(|ink| ink * available / sum);
+        }
+
+        (colors[0], colors[1], colors[2], black)
+    }
+}
+
+/// Pen of a palette with its color as `#rrggbb`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pen {
+    pub name: String,
+    pub rgb: [u8; 3],
+}
+
+/// Decomposes colors into the densities of an arbitrary set of pens.
+#[derive(Clone, Debug)]
+pub struct Palette {
+    pens: Vec<Pen>,
+
+    /// whether the pens add light to black paper instead of absorbing it from white paper
+    black_paper: bool,
+
+    /// amount of red, green and blue absorbed or, on black paper, added by each pen
+    absorbances: Vec<[f32; 3]>,
+}
+
+impl Pen {
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.rgb[0], self.rgb[1], self.rgb[2])
+    }
+}
+
+impl FromStr for Pen {
+    type Err = Error;
+
+    /// Parse `#rrggbb` or `name=#rrggbb`, using the color as name if none is given.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, color) = match s.split_once('=') {
+            Some((name, color)) => (Some(name.trim()), color.trim()),
+            None => (None, s.trim()),
+        };
+
+        let hex = color.strip_prefix('#').unwrap_or(color);
+
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(anyhow!("Invalid pen color `{}`, use #rrggbb", color));
+        }
+
+        let mut rgb = [0; 3];
+
+        for (index, channel) in rgb.iter_mut().enumerate() {
+            *channel = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16)
+                .map_err(|_| anyhow!("Invalid pen color `{}`, use #rrggbb", color))?;
+        }
+
+        let mut pen = Pen {
+            name: String::new(),
+            rgb,
+        };
+        pen.name = name.map(str::to_string).unwrap_or_else(|| pen.hex());
+        Ok(pen)
+    }
+}
+
+impl Palette {
+    pub fn new(pens: Vec<Pen>) -> Self {
+        let absorbances = pens
+            .iter()
+            .map(|pen| pen.rgb.map(|v| 1.0 - v as f32 / 255.0))
+            .collect();
+
+        Self {
+            pens,
+            black_paper: false,
+            absorbances,
+        }
+    }
+
+    /// Palette of light pens such as gel pens drawn on black paper.
+    pub fn on_black_paper(pens: Vec<Pen>) -> Self {
+        let absorbances = pens
+            .iter()
+            .map(|pen| pen.rgb.map(|v| v as f32 / 255.0))
+            .collect();
+
+        Self {
+            pens,
+            black_paper: true,
+            absorbances,
+        }
+    }
+
+    pub fn pens(&self) -> &[Pen] {
+        &self.pens
+    }
+
+    /// [`Palette::densities`] remembering the densities of colors already decomposed, which is
+    /// worthwhile since images contain few distinct colors.
+    pub fn cached_densities(&self) -> impl FnMut(u8, u8, u8) -> Vec<f32> + '_ {
+        let mut cache = HashMap::new();
+
+        move |r, g, b| {
+            cache
+                .entry([r, g, b])
+                .or_insert_with(|| self.densities(r, g, b))
+                .clone()
+        }
+    }
+
+    /// Densities of all pens that best reproduce `r`, `g` and `b` when their absorbances add up,
+    /// found by least squares restricted to densities between 0 and 1. Among equally good
+    /// combinations the one using the least ink is preferred, e.g. black instead of cyan,
+    /// magenta and yellow.
+    pub fn densities(&self, r: u8, g: u8, b: u8) -> Vec<f32> {
+        const ITERATIONS: usize = 500;
+        const INK_PENALTY: f32 = 0.01;
+
+        let mut residual = [r, g, b].map(|v| {
+            if self.black_paper {
+                v as f32 / 255.0
+            } else {
+                1.0 - v as f32 / 255.0
+            }
+        });
+        let mut densities = vec![0.0; self.pens.len()];
+        let dot = |a: &[f32; 3], b: &[f32; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        for _ in 0..ITERATIONS {
+            let mut change: f32 = 0.0;
+
+            for (density, absorbance) in densities.iter_mut().zip(&self.absorbances) {
+                let norm = dot(absorbance, absorbance);
+
+                if norm == 0.0 {
+                    continue;
+                }
+
+                let updated =
+                    (*density + (dot(&residual, absorbance) - INK_PENALTY) / norm).clamp(0.0, 1.0);
+
+                for (r, a) in residual.iter_mut().zip(absorbance) {
+                    *r -= (updated - *density) * a;
+                }
+
+                change = change.max((updated - *density).abs());
+                *density = updated;
+            }
+
+            if change < 1e-4 {
+                break;
+            }
+        }
+
+        densities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spot_colors_are_extracted() {
+        let reds: HueRange = "330-30".parse().unwrap();
+
+        assert_eq!(reds.center(), 0.0);
+        assert_eq!(from_hue(reds.center()), [255, 0, 0]);
+        assert_eq!(reds.separate(255, 0, 0), (1.0, 0.0));
+        assert_eq!(reds.separate(255, 0, 255), (0.0, 0.0));
+        assert_eq!(reds.separate(0, 0, 0), (0.0, 1.0));
+
+        let (spot, black) = reds.separate(128, 64, 64);
+        assert!((spot - 0.25).abs() < 0.01 && black == 0.0);
+    }
+
+    #[test]
+    fn palettes_decompose_colors() {
+        let palette = Palette::new(
+            ["cyan=#00ffff", "#ff00ff", "#ffff00", "#000000"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect(),
+        );
+
+        assert_eq!(palette.pens()[0].name, "cyan");
+        assert_eq!(palette.pens()[1].name, "#ff00ff");
+
+        let densities = palette.densities(255, 0, 0);
+        let expected = [0.0, 1.0, 1.0, 0.0];
+        assert!(densities
+            .iter()
+            .zip(expected)
+            .all(|(d, e)| (d - e).abs() < 0.05));
+
+        let densities = palette.densities(128, 128, 128);
+        assert!(densities.iter().all(|d| (0.0..=1.0).contains(d)));
+        assert!(densities[3] > 0.4, "{:?}", densities);
+
+        let palette = Palette::on_black_paper(
+            ["#ff0000", "#00ff00", "#0000ff"]
+                .iter()
+                .map(|s| s.parse().unwrap())
+                .collect(),
+        );
+        let densities = palette.densities(255, 255, 0);
+        let (r, g, b) = to_rgb(255, 255, 0);
+        assert!(densities
+            .iter()
+            .zip([r, g, b])
+            .all(|(d, e)| (d - e).abs() < 0.05));
+    }
+
+    #[test]
+    fn default_separation_matches_naive_conversion() {
+        let separation = Separation::default();
+
+        for (r, g, b) in [(0, 0, 0), (255, 255, 255), (10, 120, 240), (200, 30, 30)] {
+            let (c, m, y, k) = separation.separate(r, g, b);
+            let (c0, m0, y0, k0) = to_cmyk(r, g, b);
+
+            for (ink, expected) in [(c, c0), (m, m0), (y, y0), (k, k0)] {
+                assert!((ink - expected).abs() < 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_replacement_and_ink_limit() {
+        let separation = Separation {
+            black_generation: 0.5,
+            under_color_removal: 0.0,
+            ink_limit: 2.0,
+        };
+
+        let (c, m, y, k) = separation.separate(0, 0, 0);
+        assert_eq!(k, 0.5);
+        assert!((c + m + y - 1.5).abs() < 1e-6);
+        assert_eq!(c, m);
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{bail, Result};
+
+use crate::screen::Screen;
+use crate::Point;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path;
+
+/// Version of the document format written by this library.
+pub const VERSION: u32 = 1;
+
+/// Extension of files written in the binary format instead of JSON.
+pub const BINARY_EXTENSION: &str = "inkb";
+
+/// Start of binary documents.
+///
+/// The magic is followed by the length of a JSON header as a little-endian `u32` and the header,
+/// which is the document without any strokes. Then for each channel follow the number of strokes
+/// and for each stroke the number of points as `u32` and the points as pairs of `f32`.
+const MAGIC: &[u8; 8] = b"INKDROP\0";
+
+const CMYK: [&str; 4] = ["cyan", "magenta", "yellow", "black"];
+
+/// Whether the strokes of a document are connected paths or loose points.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    #[default]
+    Paths,
+
+    /// every point is drawn as a dot of its own
+    Points,
+}
+
+/// Parameters of the run that produced a document, as far as they are known.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_points: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cmyk: Option<bool>,
+
+    /// whether light pens are drawn on black paper
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub black_paper: Option<bool>,
+
+    /// number of points of each channel if they were not divided by density
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_points: Option<Vec<usize>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_gamma: Option<Vec<f32>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voronoi_iterations: Option<usize>,
+
+    /// minimum distance between points of different channels during relaxation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_distance: Option<f64>,
+
+    /// halftone screen of each channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screens: Option<Vec<Screen>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tsp_improvement: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Channel {
+    /// name of the ink, e.g. `cyan`
+    pub name: String,
+
+    /// SVG color used to draw the channel
+    pub color: String,
+
+    /// continuous lines in image coordinates, or a single list of points for point documents
+    pub strokes: Vec<Vec<Point>>,
+}
+
+/// Points or paths of all channels of an image.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Document {
+    pub version: u32,
+
+    /// dimensions of the source image in pixels, unknown for the legacy format
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    #[serde(default)]
+    pub kind: Kind,
+
+    #[serde(default)]
+    pub settings: Settings,
+
+    pub channels: Vec<Channel>,
+}
+
+impl Channel {
+    /// Channel `index` of `count` channels named after the CMYK inks if there are four of them.
+    pub fn numbered(index: usize, count: usize, strokes: Vec<Vec<Point>>) -> Self {
+        let (name, color) = if count == CMYK.len() {
+            (CMYK[index].to_string(), CMYK[index].to_string())
+        } else {
+            (format!("channel {}", index), "black".to_string())
+        };
+
+        Self {
+            name,
+            color,
+            strokes,
+        }
+    }
+}
+
+impl Document {
+    /// Create a document with one stroke per channel from the point sets or tours of `inkdrop`.
+    pub fn new(
+        width: u32,
+        height: u32,
+        kind: Kind,
+        settings: Settings,
+        point_sets: &[Vec<Point>],
+    ) -> Self {
+        Self {
+            version: VERSION,
+            width: Some(width),
+            height: Some(height),
+            kind,
+            settings,
+            channels: numbered(point_sets),
+        }
+    }
+
+    /// Create a document with the given channels.
+    pub fn with_channels(
+        width: u32,
+        height: u32,
+        kind: Kind,
+        settings: Settings,
+        channels: Vec<Channel>,
+    ) -> Self {
+        Self {
+            version: VERSION,
+            width: Some(width),
+            height: Some(height),
+            kind,
+            settings,
+            channels,
+        }
+    }
+
+    /// Replace the strokes of each channel with one stroke of `point_sets`, keeping the names
+    /// and colors of the channels.
+    pub fn with_point_sets(self, kind: Kind, point_sets: Vec<Vec<Point>>) -> Self {
+        let channels = self
+            .channels
+            .into_iter()
+            .zip(point_sets)
+            .map(|(channel, points)| Channel {
+                strokes: vec![points],
+                ..channel
+            })
+            .collect();
+
+        Self {
+            kind,
+            channels,
+            ..self
+        }
+    }
+
+    /// SVG colors of all channels.
+    pub fn colors(&self) -> Vec<&str> {
+        self.channels.iter().map(|c| c.color.as_str()).collect()
+    }
+
+    /// Strokes of each channel as they are drawn, i.e. every point on its own for point
+    /// documents.
+    pub fn strokes(&self) -> Vec<Vec<Vec<Point>>> {
+        self.channels
+            .iter()
+            .map(|channel| match self.kind {
+                Kind::Paths => channel.strokes.clone(),
+                Kind::Points => channel.strokes.iter().flatten().map(|p| vec![*p]).collect(),
+            })
+            .collect()
+    }
+
+    /// Parse a binary or JSON document or one of the bare arrays of points written by earlier
+    /// versions.
+    pub fn from_reader<R: BufRead>(mut reader: R) -> Result<Self> {
+        if reader.fill_buf()?.starts_with(MAGIC) {
+            return Self::from_binary(reader);
+        }
+
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+
+        if value.is_array() {
+            let point_sets = serde_json::from_value::<Vec<Vec<Point>>>(value.clone())
+                .or_else(|_| serde_json::from_value::<Vec<Point>>(value).map(|c| vec![c]))?;
+
+            return Ok(Self {
+                version: VERSION,
+                width: None,
+                height: None,
+                kind: Kind::Paths,
+                settings: Settings::default(),
+                channels: numbered(&point_sets),
+            });
+        }
+
+        Self::checked(serde_json::from_value(value)?)
+    }
+
+    fn checked(document: Document) -> Result<Self> {
+        if document.version > VERSION {
+            bail!(
+                "Document version {} is not supported, only up to {}",
+                document.version,
+                VERSION
+            );
+        }
+
+        Ok(document)
+    }
+
+    fn from_binary<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+
+        let length = read_u32(&mut reader)?;
+        let header = read_bytes(&mut reader, length as u64)?;
+        let mut document = Self::checked(serde_json::from_slice(&header)?)?;
+
+        for channel in document.channels.iter_mut() {
+            let num_strokes = read_u32(&mut reader)?;
+
+            channel.strokes = (0..num_strokes)
+                .map(|_| {
+                    let num_points = read_u32(&mut reader)?;
+                    let buffer = read_bytes(&mut reader, num_points as u64 * 8)?;
+
+                    Ok(buffer
+                        .chunks_exact(8)
+                        .map(|xy| {
+                            let x = f32::from_le_bytes(xy[..4].try_into().unwrap());
+                            let y = f32::from_le_bytes(xy[4..].try_into().unwrap());
+                            Point::new(x as f64, y as f64)
+                        })
+                        .collect())
+                })
+                .collect::<Result<_>>()?;
+        }
+
+        Ok(document)
+    }
+
+    /// Write the document in the binary format, storing coordinates with single precision.
+    pub fn to_binary<W: Write>(&self, mut writer: W) -> Result<()> {
+        let header = Document {
+            channels: self
+                .channels
+                .iter()
+                .map(|channel| Channel {
+                    strokes: Vec::new(),
+                    ..channel.clone()
+                })
+                .collect(),
+            ..self.clone()
+        };
+        let header = serde_json::to_vec(&header)?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&header)?;
+
+        for channel in &self.channels {
+            writer.write_all(&(channel.strokes.len() as u32).to_le_bytes())?;
+
+            for stroke in &channel.strokes {
+                writer.write_all(&(stroke.len() as u32).to_le_bytes())?;
+
+                for point in stroke {
+                    writer.write_all(&(point.x as f32).to_le_bytes())?;
+                    writer.write_all(&(point.y as f32).to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a document in any format.
+    pub fn read(filename: &path::Path) -> Result<Self> {
+        Self::from_reader(BufReader::new(std::fs::File::open(filename)?))
+    }
+
+    /// Write the document in binary format if `filename` ends with [`BINARY_EXTENSION`] and as
+    /// JSON otherwise.
+    pub fn write(&self, filename: &path::Path) -> Result<()> {
+        let mut fh = BufWriter::new(std::fs::File::create(filename)?);
+
+        if filename.extension().is_some_and(|e| e == BINARY_EXTENSION) {
+            self.to_binary(&mut fh)?;
+        } else {
+            serde_json::to_writer_pretty(&mut fh, self)?;
+        }
+
+        fh.flush()?;
+        Ok(())
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buffer = [0; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+/// Read `length` bytes, growing the buffer only as far as the input goes, so that corrupt
+/// lengths cannot exhaust memory.
+fn read_bytes<R: Read>(reader: &mut R, length: u64) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    reader.take(length).read_to_end(&mut buffer)?;
+
+    if (buffer.len() as u64) < length {
+        bail!("Document is truncated");
+    }
+
+    Ok(buffer)
+}
+
+fn numbered(point_sets: &[Vec<Point>]) -> Vec<Channel> {
+    point_sets
+        .iter()
+        .enumerate()
+        .map(|(index, points)| Channel::numbered(index, point_sets.len(), vec![points.clone()]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_arrays_are_read() {
+        let channel = r#"[{"x": 1.0, "y": 2.0}, {"x": 3.0, "y": 4.0}]"#;
+        let document = Document::from_reader(channel.as_bytes()).unwrap();
+        assert_eq!(document.channels.len(), 1);
+        assert_eq!(document.channels[0].name, "channel 0");
+        assert_eq!(document.width, None);
+
+        let channels = format!("[{0}, [], [], {0}]", channel);
+        let document = Document::from_reader(channels.as_bytes()).unwrap();
+        assert_eq!(document.channels[3].name, "black");
+        assert_eq!(
+            document.channels[3].strokes,
+            vec![vec![Point::new(1., 2.), Point::new(3., 4.)]]
+        );
+    }
+
+    #[test]
+    fn documents_round_trip() {
+        let settings = Settings {
+            num_points: Some(2),
+            ..Settings::default()
+        };
+        let document = Document::new(
+            10,
+            20,
+            Kind::Points,
+            settings,
+            &[vec![Point::new(1., 2.), Point::new(3., 4.)]],
+        );
+
+        let json = serde_json::to_string(&document).unwrap();
+        assert_eq!(Document::from_reader(json.as_bytes()).unwrap(), document);
+        assert_eq!(document.strokes()[0].len(), 2);
+
+        let mut binary = Vec::new();
+        document.to_binary(&mut binary).unwrap();
+        assert_eq!(Document::from_reader(binary.as_slice()).unwrap(), document);
+
+        // The last stroke is its number of points followed by two points.
+        let mut corrupt = binary.clone();
+        let count = corrupt.len() - 20;
+        corrupt[count..count + 4].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        assert!(Document::from_reader(corrupt.as_slice()).is_err());
+
+        binary.truncate(binary.len() - 4);
+        assert!(Document::from_reader(binary.as_slice()).is_err());
+
+        let newer = json.replace(r#""version":1"#, r#""version":2"#);
+        assert!(Document::from_reader(newer.as_bytes()).is_err());
+    }
+}
@@ -1,27 +1,38 @@
+mod dialect;
+mod kinematics;
+mod placement;
+pub mod preview;
+pub mod resume;
+pub mod sender;
+
 use crate::point::Point;
-use askama::Template;
-use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::path;
+
+pub use dialect::Dialect;
+pub use kinematics::{Cartesian, CoreXY, Kinematics, Machine, Polargraph};
+pub use placement::{Anchor, Placement, Transform};
 
 pub type Channel = Vec<Point>;
 pub type Channels = Vec<Channel>;
 
-#[derive(Template)]
-#[template(path = "template.gcode", escape = "none")]
-struct GcodeTemplate<'a> {
-    calibration: &'a Calibration,
-    channel: &'a Channel,
+/// Continuous line drawn without lifting the pen.
+pub type Stroke = Vec<Point>;
+
+/// Strokes of one channel drawn with the same pen.
+pub struct Layer<'a> {
+    pub name: &'a str,
+    pub strokes: &'a [Stroke],
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Calibration {
-    /// base width of the machine, i.e., the distance
-    /// between the shafts of the stepper
-    /// motors
-    pub base_width: f64,
-
-    /// vertical distance between the center of the drawing
-    /// plane and the shafts of the stepper motors
-    pub base_height: f64,
+    /// kinematics of the machine selected by the `kinematics`
+    /// key together with its machine specific measurements,
+    /// defaults to a polargraph if the key is missing
+    #[serde(flatten)]
+    pub machine: Machine,
 
     /// maximal with of the drawing plane, gcode will
     /// be scaled to fill the area, you can set this to
@@ -34,85 +45,320 @@ pub struct Calibration {
     /// a lower value to have smaller result images. Ratio
     /// will be preserved.
     pub drawing_height: f64,
+
+    /// maximal length of a straight segment in drawing
+    /// coordinates, longer segments are subdivided before
+    /// the transformation so that they are drawn as straight
+    /// lines instead of curves. Subdivision is disabled if
+    /// not set.
+    #[serde(default, deserialize_with = "positive")]
+    pub max_segment_length: Option<f64>,
+
+    /// minimal distance in millimeters between any drawn
+    /// point and the border of the drawing plane as well as
+    /// the mechanical limits of the machine, e.g. the line
+    /// between the motors of a polargraph
+    #[serde(default)]
+    pub margin: f64,
+
+    /// gcode flavour understood by the controller
+    #[serde(default)]
+    pub dialect: Dialect,
+
+    /// gcode lifting the pen before travel moves, overrides
+    /// the default of the dialect
+    #[serde(default)]
+    pub pen_up: Option<String>,
+
+    /// gcode lowering the pen after travel moves, overrides
+    /// the default of the dialect
+    #[serde(default)]
+    pub pen_down: Option<String>,
+
+    /// gcode pausing the program to change the pen between
+    /// the channels of a combined file, `{name}` is replaced
+    /// by the name of the next channel, overrides the default
+    /// of the dialect
+    #[serde(default)]
+    pub pen_change: Option<String>,
+
+    /// feed rate of drawing moves in millimeters per minute,
+    /// overrides the default of the dialect
+    #[serde(default, deserialize_with = "positive")]
+    pub feed_rate: Option<f64>,
 }
 
-impl Calibration {
-    pub fn gcode(&self, channel: &Channel) -> String {
-        let tpl = GcodeTemplate {
-            channel,
-            calibration: self,
-        };
-        tpl.render().unwrap()
+/// Deserialize an optional length or rate that must be positive and finite if given.
+fn positive<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let value = Option::<f64>::deserialize(deserializer)?;
+
+    match value {
+        Some(v) if !(v.is_finite() && v > 0.0) => Err(serde::de::Error::custom(format!(
+            "{} is not a positive number",
+            v
+        ))),
+        _ => Ok(value),
     }
+}
 
-    pub fn translate_origin(&self, channels: &Channels) -> Channels {
-        let mut pts = Vec::new();
-        for c in channels {
-            pts.append(&mut c.clone());
+/// Clip the segment from `start` to `end` to the rectangle spanned by `min` and `max` using the Liang-Barsky algorithm.
+fn clip_segment(start: Point, end: Point, min: Point, max: Point) -> Option<(Point, Point)> {
+    let delta = end - start;
+    let mut t0: f64 = 0.0;
+    let mut t1: f64 = 1.0;
+
+    for (p, q) in [
+        (-delta.x, start.x - min.x),
+        (delta.x, max.x - start.x),
+        (-delta.y, start.y - min.y),
+        (delta.y, max.y - start.y),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
         }
+    }
 
-        let min_x = pts
-            .iter()
-            .map(|p| p.x)
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
+    (t0 <= t1).then(|| (start + delta * t0, start + delta * t1))
+}
 
-        let min_y = pts
-            .iter()
-            .map(|p| p.y)
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let max_x = pts
-            .iter()
-            .map(|p| p.x)
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let max_y = pts
-            .iter()
-            .map(|p| p.y)
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
+/// Insert intermediate points such that no segment of `channel` is longer than `max_length`,
+/// which leaves `channel` unchanged unless `max_length` is positive and finite.
+pub fn subdivide(channel: &Channel, max_length: f64) -> Channel {
+    if !(max_length.is_finite() && max_length > 0.0) {
+        return channel.clone();
+    }
+
+    let mut result = Vec::with_capacity(channel.len());
+
+    if let Some(first) = channel.first() {
+        result.push(*first);
+    }
+
+    for segment in channel.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        let steps = (start.distance(&end) / max_length).ceil().max(1.0) as usize;
+
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            result.push(start + (end - start) * t);
+        }
+    }
+
+    result
+}
+
+impl Calibration {
+    pub fn read(filename: &path::Path) -> Result<Self> {
+        let fh = std::io::BufReader::new(std::fs::File::open(filename)?);
+        Ok(serde_json::from_reader(fh)?)
+    }
+
+    /// Command lifting the pen, the one of the calibration or else of the dialect.
+    pub fn pen_up(&self) -> Option<&str> {
+        self.pen_up.as_deref().or(self.dialect.pen_up())
+    }
+
+    /// Command lowering the pen, the one of the calibration or else of the dialect.
+    pub fn pen_down(&self) -> Option<&str> {
+        self.pen_down.as_deref().or(self.dialect.pen_down())
+    }
+
+    /// Command pausing for a pen change, the one of the calibration or else of the dialect.
+    pub fn pen_change(&self) -> &str {
+        self.pen_change
+            .as_deref()
+            .unwrap_or_else(|| self.dialect.pen_change())
+    }
+
+    /// Feed rate of drawing moves, the one of the calibration or else of the dialect.
+    pub fn feed_rate(&self) -> f64 {
+        self.feed_rate.unwrap_or_else(|| self.dialect.feed_rate())
+    }
 
-        let dx = max_x - min_x;
-        let dy = max_y - min_y;
+    /// Generate a program drawing `strokes` given in machine coordinates with the template of
+    /// the calibrated dialect.
+    pub fn gcode(&self, strokes: &[Stroke]) -> String {
+        let layers = [Layer { name: "", strokes }];
+        dialect::render(self.dialect.template(), self, &layers, false).unwrap()
+    }
 
-        let ratio_x = self.drawing_width / dx;
+    /// Generate a single program drawing all `layers` in order, pausing before each of them
+    /// to let the operator insert the right pen.
+    pub fn combined_gcode(&self, layers: &[Layer]) -> String {
+        dialect::render(self.dialect.template(), self, layers, true).unwrap()
+    }
 
-        let ratio_y = self.drawing_height / dy;
-        let ratio = ratio_x.min(ratio_y);
+    /// Generate a program with a user supplied `template` instead of the one of the dialect.
+    pub fn render(&self, template: &str, layers: &[Layer], pen_change: bool) -> Result<String> {
+        dialect::render(template, self, layers, pen_change)
+    }
 
-        // equation: x_max + offset == dx/2
-        let offset_x = 0.5 * dx - max_x;
-        let offset_y = 0.5 * dy - max_y;
+    /// Upper left and lower right corner of the area that can be drawn in, i.e. the drawing
+    /// plane reduced by the margin and the limits of the machine.
+    pub fn area(&self) -> (Point, Point) {
+        // Tolerance for rounding errors of drawings scaled to fill the area.
+        const EPSILON: f64 = 1e-9;
 
-        let mut result = Vec::new();
+        let (min, max) = self.machine.limits(self.margin - EPSILON);
+        let half_width = 0.5 * self.drawing_width - self.margin + EPSILON;
+        let half_height = 0.5 * self.drawing_height - self.margin + EPSILON;
 
-        for c in channels {
-            let mut transformed_channel = Vec::with_capacity(c.len());
-            for pt in c {
-                transformed_channel.push(Point::new(
-                    (pt.x + offset_x) * ratio,
-                    (pt.y + offset_y) * ratio,
+        (
+            Point::new(min.x.max(-half_width), min.y.max(-half_height)),
+            Point::new(max.x.min(half_width), max.y.min(half_height)),
+        )
+    }
+
+    /// Check that all points of the translated `channels` lie inside [`Calibration::area`].
+    pub fn validate(&self, channels: &Channels) -> Result<()> {
+        let (min, max) = self.area();
+
+        for (index, channel) in channels.iter().enumerate() {
+            if let Some(pt) = channel
+                .iter()
+                .find(|p| p.x < min.x || p.x > max.x || p.y < min.y || p.y > max.y)
+            {
+                return Err(anyhow!(
+                    "Point ({:.2}, {:.2}) of channel {} lies outside of the drawing area from ({:.2}, {:.2}) to ({:.2}, {:.2})",
+                    pt.x, pt.y, index, min.x, min.y, max.x, max.y
                 ));
             }
-            result.push(transformed_channel);
         }
 
-        result
+        Ok(())
+    }
+
+    /// Split `channel` into strokes that lie inside [`Calibration::area`], cutting segments at
+    /// its border.
+    pub fn clip(&self, channel: &Channel) -> Vec<Stroke> {
+        let (min, max) = self.area();
+        let mut strokes = Vec::new();
+        let mut stroke: Stroke = Vec::new();
+
+        if let [pt] = channel.as_slice() {
+            if clip_segment(*pt, *pt, min, max).is_some() {
+                strokes.push(vec![*pt]);
+            }
+        }
+
+        for segment in channel.windows(2) {
+            match clip_segment(segment[0], segment[1], min, max) {
+                Some((start, end)) => {
+                    if stroke.last() != Some(&start) {
+                        if !stroke.is_empty() {
+                            strokes.push(stroke);
+                        }
+                        stroke = vec![start];
+                    }
+                    stroke.push(end);
+                }
+                None => {
+                    if !stroke.is_empty() {
+                        strokes.push(std::mem::take(&mut stroke));
+                    }
+                }
+            }
+        }
+
+        if !stroke.is_empty() {
+            strokes.push(stroke);
+        }
+
+        strokes
+    }
+
+    /// Scale and center `channels` to fill the drawing area.
+    pub fn translate_origin(&self, channels: &Channels) -> Channels {
+        self.place(channels, &Placement::default())
+    }
+
+    /// Compute the single transform that places all `channels` on the drawing area.
+    pub fn fit(&self, channels: &Channels, placement: &Placement) -> Transform {
+        let mut points = channels.iter().flatten();
+        let first = points.next().copied().unwrap_or_else(Point::origin);
+        let (min, max) = points.fold((first, first), |(min, max), p| {
+            (
+                Point::new(min.x.min(p.x), min.y.min(p.y)),
+                Point::new(max.x.max(p.x), max.y.max(p.y)),
+            )
+        });
+
+        placement.transform(
+            min,
+            max,
+            self.drawing_width - 2.0 * self.margin,
+            self.drawing_height - 2.0 * self.margin,
+        )
+    }
+
+    /// Place `channels` on the drawing area using one common transform for all of them.
+    pub fn place(&self, channels: &Channels, placement: &Placement) -> Channels {
+        let transform = self.fit(channels, placement);
+
+        channels
+            .iter()
+            .map(|c| c.iter().map(|pt| transform.apply(pt)).collect())
+            .collect()
+    }
+
+    /// Place the strokes of all `channels` with one common transform and cut them at the border
+    /// of [`Calibration::area`] if `clip` is set or fail if they do not fit otherwise.
+    pub fn layout(
+        &self,
+        channels: &[Vec<Stroke>],
+        placement: &Placement,
+        clip: bool,
+    ) -> Result<Vec<Vec<Stroke>>> {
+        let transform = self.fit(&channels.concat(), placement);
+        let placed = channels
+            .iter()
+            .map(|strokes| {
+                strokes
+                    .iter()
+                    .map(|stroke| stroke.iter().map(|pt| transform.apply(pt)).collect())
+                    .collect::<Vec<Stroke>>()
+            })
+            .collect::<Vec<_>>();
+
+        if clip {
+            return Ok(placed
+                .iter()
+                .map(|strokes| strokes.iter().flat_map(|s| self.clip(s)).collect())
+                .collect());
+        }
+
+        self.validate(&placed.iter().map(|strokes| strokes.concat()).collect())?;
+        Ok(placed)
     }
 
     pub fn apply(&self, pt: &Point) -> Point {
-        let pt = Point::new(pt.x, -pt.y);
-        let a =
-            ((0.5 * self.base_width + pt.x).powf(2.0) + (self.base_height - pt.y).powf(2.0)).sqrt();
-        let b =
-            ((0.5 * self.base_width - pt.x).powf(2.0) + (self.base_height - pt.y).powf(2.0)).sqrt();
+        self.machine.apply(pt)
+    }
 
-        Point::new(a, b)
+    /// Inverse of [`Calibration::apply`].
+    pub fn invert(&self, pt: &Point) -> Point {
+        self.machine.invert(pt)
+    }
+
+    /// Map a point relative to the home position as written to gcode back to drawing
+    /// coordinates.
+    pub fn restore(&self, pt: &Point) -> Point {
+        self.invert(&(*pt + self.apply(&Point::origin())))
     }
 
     fn transform_single_channel(&self, channel: &Channel) -> Channel {
         let home = self.apply(&Point::origin());
+        let channel = match self.max_segment_length {
+            Some(max_length) => subdivide(channel, max_length),
+            None => channel.clone(),
+        };
+
         channel
             .iter()
             .map(|pt| self.apply(pt))
@@ -132,14 +378,31 @@ impl Calibration {
 mod tests {
     use super::*;
 
+    /// Calibration of `machine` with all optional keys at their defaults.
+    fn calibration(machine: Machine, width: f64, height: f64, margin: f64) -> Calibration {
+        let mut calib: Calibration = serde_json::from_str(
+            r#"{"kinematics": "cartesian", "drawing_width": 0, "drawing_height": 0}"#,
+        )
+        .unwrap();
+
+        calib.machine = machine;
+        calib.drawing_width = width;
+        calib.drawing_height = height;
+        calib.margin = margin;
+        calib
+    }
+
     #[test]
     fn conversion_of_coordinates_works() {
-        let calib = Calibration {
-            base_width: 80.1,
-            base_height: 57.3,
-            drawing_width: 50.,
-            drawing_height: 50.,
-        };
+        let calib = calibration(
+            Machine::Polargraph(Polargraph {
+                base_width: 80.1,
+                base_height: 57.3,
+            }),
+            50.,
+            50.,
+            0.,
+        );
 
         assert_eq!(
             calib.apply(&Point::origin()),
@@ -149,12 +412,15 @@ mod tests {
 
     #[test]
     fn translation_works() {
-        let calib = Calibration {
-            base_width: 10.,
-            base_height: 20.,
-            drawing_width: 50.,
-            drawing_height: 100.,
-        };
+        let calib = calibration(
+            Machine::Polargraph(Polargraph {
+                base_width: 10.,
+                base_height: 20.,
+            }),
+            50.,
+            100.,
+            0.,
+        );
 
         let channels = vec![vec![Point::new(-1., 15.), Point::new(4., -5.)]];
 
@@ -166,4 +432,172 @@ mod tests {
 
         assert_eq!(result, calib.translate_origin(&result));
     }
+
+    #[test]
+    fn placement_shares_transform() {
+        let calib = calibration(
+            Machine::Cartesian(Cartesian { invert_y: false }),
+            100.,
+            50.,
+            0.,
+        );
+
+        let placement = Placement {
+            scale: Some(0.5),
+            anchor: Anchor::TopLeft,
+            offset: Point::new(1., 2.),
+        };
+
+        let channels = vec![vec![Point::new(10., 20.)], vec![Point::new(30., 40.)]];
+
+        assert_eq!(
+            calib.place(&channels, &placement),
+            vec![vec![Point::new(-49., -23.)], vec![Point::new(-39., -13.)]]
+        );
+    }
+
+    #[test]
+    fn inversion_works() {
+        let pt = Point::new(-20.5, 13.25);
+        let machines = [
+            Machine::Polargraph(Polargraph {
+                base_width: 300.,
+                base_height: 560.,
+            }),
+            Machine::Cartesian(Cartesian { invert_y: true }),
+            Machine::CoreXY(CoreXY { invert_y: false }),
+        ];
+
+        for machine in machines {
+            assert_eq!(machine.invert(&machine.apply(&pt)), pt);
+        }
+    }
+
+    #[test]
+    fn kinematics_are_selected_by_calibration() {
+        let legacy: Calibration = serde_json::from_str(
+            r#"{"base_width": 300, "base_height": 560, "drawing_width": 300, "drawing_height": 450}"#,
+        )
+        .unwrap();
+        assert!(matches!(legacy.machine, Machine::Polargraph(_)));
+
+        let corexy: Calibration = serde_json::from_str(
+            r#"{"kinematics": "corexy", "drawing_width": 300, "drawing_height": 450}"#,
+        )
+        .unwrap();
+        assert_eq!(corexy.apply(&Point::new(2., 1.)), Point::new(3., 1.));
+
+        let typo = serde_json::from_str::<Calibration>(
+            r#"{"kinematics": "coreXY", "base_width": 300, "base_height": 560, "drawing_width": 300, "drawing_height": 450}"#,
+        )
+        .unwrap_err();
+        assert!(typo.to_string().contains("unknown variant `coreXY`"));
+
+        let missing = serde_json::from_str::<Calibration>(
+            r#"{"base_width": 300, "drawing_width": 300, "drawing_height": 450}"#,
+        )
+        .unwrap_err();
+        assert!(missing.to_string().contains("missing field `base_height`"));
+    }
+
+    #[test]
+    fn clipping_splits_strokes() {
+        let calib = calibration(
+            Machine::Cartesian(Cartesian { invert_y: false }),
+            12.,
+            12.,
+            1.,
+        );
+
+        let channel = vec![
+            Point::new(0., 0.),
+            Point::new(10., 0.),
+            Point::new(10., 2.),
+            Point::new(0., 2.),
+        ];
+
+        assert!(calib.validate(&vec![channel.clone()]).is_err());
+        assert_eq!(
+            calib.clip(&channel),
+            vec![
+                vec![Point::new(0., 0.), Point::new(5., 0.)],
+                vec![Point::new(5., 2.), Point::new(0., 2.)]
+            ]
+        );
+    }
+
+    #[test]
+    fn dialects_render_moves() {
+        let mut calib: Calibration = serde_json::from_str(
+            r#"{"kinematics": "cartesian", "drawing_width": 10, "drawing_height": 10}"#,
+        )
+        .unwrap();
+
+        let strokes = vec![vec![Point::new(1., 2.), Point::new(3., 4.)]];
+        let layers = [
+            Layer {
+                name: "cyan",
+                strokes: &strokes,
+            },
+            Layer {
+                name: "black",
+                strokes: &strokes,
+            },
+        ];
+
+        for dialect in [
+            Dialect::Generic,
+            Dialect::Grbl,
+            Dialect::Marlin,
+            Dialect::RepRap,
+        ] {
+            calib.dialect = dialect;
+            let gcode = calib.combined_gcode(&layers);
+            let moves = preview::parse(&gcode)
+                .into_iter()
+                .filter(|m| m.draw)
+                .collect::<Vec<_>>();
+
+            assert!(gcode.contains("black pen"));
+            assert_eq!(calib.pen_down().is_some(), dialect != Dialect::Generic);
+            assert_eq!(moves.len(), 2);
+            assert_eq!(moves[0].to, Point::new(3., 4.));
+        }
+    }
+
+    #[test]
+    fn calibration_overrides_dialect() {
+        let calib: Calibration = serde_json::from_str(
+            r#"{"kinematics": "cartesian", "drawing_width": 10, "drawing_height": 10, "dialect": "grbl", "pen_up": "M3 S0"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(calib.pen_up(), Some("M3 S0"));
+        assert_eq!(calib.pen_down(), Some("M3 S1000"));
+        assert!(calib.gcode(&[vec![Point::new(1., 2.)]]).contains("M3 S0"));
+    }
+
+    #[test]
+    fn subdivision_works() {
+        let channel = vec![Point::new(0., 0.), Point::new(3., 0.), Point::new(3., 1.)];
+
+        assert_eq!(
+            subdivide(&channel, 1.5),
+            vec![
+                Point::new(0., 0.),
+                Point::new(1.5, 0.),
+                Point::new(3., 0.),
+                Point::new(3., 1.)
+            ]
+        );
+        assert_eq!(subdivide(&channel, 0.), channel);
+
+        for (length, valid) in [("1.5", true), ("0", false), ("-1", false)] {
+            let json = format!(
+                r#"{{"kinematics": "cartesian", "drawing_width": 10, "drawing_height": 10, "max_segment_length": {}}}"#,
+                length
+            );
+            assert_eq!(serde_json::from_str::<Calibration>(&json).is_ok(), valid);
+        }
+    }
 }
@@ -0,0 +1,191 @@
+use super::{Calibration, Layer};
+use crate::point::Point;
+use anyhow::{anyhow, Error, Result};
+use minijinja::Environment;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Built-in gcode flavours.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// bare `g0`/`g1` moves with optional pen commands
+    #[default]
+    Generic,
+    Grbl,
+    Marlin,
+    RepRap,
+}
+
+#[derive(Serialize)]
+struct StrokeContext<'a> {
+    index: usize,
+
+    /// length in millimeters on paper
+    length: f64,
+
+    /// points in machine coordinates
+    points: &'a [Point],
+}
+
+#[derive(Serialize)]
+struct LayerContext<'a> {
+    index: usize,
+    name: &'a str,
+    strokes: Vec<StrokeContext<'a>>,
+}
+
+#[derive(Default, Serialize)]
+struct Statistics {
+    layers: usize,
+    strokes: usize,
+    points: usize,
+    draw_distance: f64,
+    travel_distance: f64,
+}
+
+#[derive(Serialize)]
+struct Context<'a> {
+    calibration: &'a Calibration,
+    machine: String,
+    pen_up: Option<&'a str>,
+    pen_down: Option<&'a str>,
+    pen_change_command: &'a str,
+    feed_rate: f64,
+    layers: Vec<LayerContext<'a>>,
+    pen_change: bool,
+    stats: Statistics,
+}
+
+impl Dialect {
+    /// Source of the template generating gcode of this dialect.
+    pub fn template(&self) -> &'static str {
+        match self {
+            Dialect::Generic => include_str!("../../templates/generic.gcode"),
+            Dialect::Grbl => include_str!("../../templates/grbl.gcode"),
+            Dialect::Marlin => include_str!("../../templates/marlin.gcode"),
+            Dialect::RepRap => include_str!("../../templates/reprap.gcode"),
+        }
+    }
+
+    /// Default command lifting the pen, none for bare moves.
+    pub fn pen_up(&self) -> Option<&'static str> {
+        match self {
+            Dialect::Generic => None,
+            Dialect::Grbl => Some("M5"),
+            Dialect::Marlin => Some("M280 P0 S90"),
+            Dialect::RepRap => Some("G0 Z5"),
+        }
+    }
+
+    /// Default command lowering the pen, none for bare moves.
+    pub fn pen_down(&self) -> Option<&'static str> {
+        match self {
+            Dialect::Generic => None,
+            Dialect::Grbl => Some("M3 S1000"),
+            Dialect::Marlin => Some("M280 P0 S30"),
+            Dialect::RepRap => Some("G1 Z0"),
+        }
+    }
+
+    /// Default command pausing for a pen change, `{name}` is replaced by the name of the pen.
+    pub fn pen_change(&self) -> &'static str {
+        match self {
+            Dialect::Generic | Dialect::Grbl => "M0 ; insert {name} pen",
+            Dialect::Marlin => "M0 Insert {name} pen",
+            Dialect::RepRap => "M226 ; insert {name} pen",
+        }
+    }
+
+    /// Default feed rate of drawing moves in millimeters per minute.
+    pub fn feed_rate(&self) -> f64 {
+        3000.0
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Dialect::Generic),
+            "grbl" => Ok(Dialect::Grbl),
+            "marlin" => Ok(Dialect::Marlin),
+            "reprap" => Ok(Dialect::RepRap),
+            _ => Err(anyhow!("Unknown dialect `{}`", s)),
+        }
+    }
+}
+
+/// Render `template` for `layers` given in machine coordinates.
+pub(super) fn render(
+    template: &str,
+    calibration: &Calibration,
+    layers: &[Layer],
+    pen_change: bool,
+) -> Result<String> {
+    let mut stats = Statistics::default();
+    let mut position = calibration.restore(&Point::origin());
+
+    let layers = layers
+        .iter()
+        .enumerate()
+        .map(|(index, layer)| {
+            let strokes = layer
+                .strokes
+                .iter()
+                .filter(|stroke| !stroke.is_empty())
+                .enumerate()
+                .map(|(index, points)| {
+                    let restored = points
+                        .iter()
+                        .map(|p| calibration.restore(p))
+                        .collect::<Vec<_>>();
+
+                    let length = restored
+                        .windows(2)
+                        .map(|pair| pair[0].distance(&pair[1]))
+                        .sum();
+
+                    stats.travel_distance += position.distance(&restored[0]);
+                    stats.draw_distance += length;
+                    stats.points += points.len();
+                    position = *restored.last().unwrap();
+
+                    StrokeContext {
+                        index,
+                        length,
+                        points,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            stats.strokes += strokes.len();
+
+            LayerContext {
+                index,
+                name: layer.name,
+                strokes,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    stats.layers = layers.len();
+
+    let context = Context {
+        calibration,
+        machine: calibration.machine.to_string(),
+        pen_up: calibration.pen_up(),
+        pen_down: calibration.pen_down(),
+        pen_change_command: calibration.pen_change(),
+        feed_rate: calibration.feed_rate(),
+        layers,
+        pen_change,
+        stats,
+    };
+
+    let mut env = Environment::new();
+    env.add_template("gcode", template)?;
+
+    Ok(env.get_template("gcode")?.render(context)?)
+}
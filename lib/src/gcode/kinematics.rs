@@ -0,0 +1,198 @@
+use crate::point::Point;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Mapping from drawing coordinates to machine axis positions.
+///
+/// Drawing coordinates are given in millimeters relative to the center of the drawing area with
+/// the y axis pointing down, just like the image coordinates they originate from.
+pub trait Kinematics {
+    /// Map a point in drawing coordinates to machine coordinates.
+    fn apply(&self, pt: &Point) -> Point;
+
+    /// Map machine coordinates back to a point in drawing coordinates.
+    fn invert(&self, pt: &Point) -> Point;
+
+    /// Upper left and lower right corner of the area the machine can draw in while keeping
+    /// `margin` millimeters away from its mechanical limits.
+    fn limits(&self, _margin: f64) -> (Point, Point) {
+        (
+            Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            Point::new(f64::INFINITY, f64::INFINITY),
+        )
+    }
+}
+
+/// Two motors at the top corners with the pen hanging from their belts.
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+pub struct Polargraph {
+    /// base width of the machine, i.e., the distance
+    /// between the shafts of the stepper
+    /// motors
+    pub base_width: f64,
+
+    /// vertical distance between the center of the drawing
+    /// plane and the shafts of the stepper motors
+    pub base_height: f64,
+}
+
+/// Plain XY plotter with one motor per axis.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Cartesian {
+    /// flip the y axis for machines whose y axis points up
+    #[serde(default)]
+    pub invert_y: bool,
+}
+
+/// CoreXY machine driven directly by its A and B motor positions. If the firmware does the
+/// CoreXY mixing itself, use [`Cartesian`] instead.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CoreXY {
+    /// flip the y axis for machines whose y axis points up
+    #[serde(default)]
+    pub invert_y: bool,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
+#[serde(
+    try_from = "Map<String, Value>",
+    tag = "kinematics",
+    rename_all = "lowercase"
+)]
+pub enum Machine {
+    Polargraph(Polargraph),
+    Cartesian(Cartesian),
+    CoreXY(CoreXY),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kinematics", rename_all = "lowercase")]
+enum TaggedMachine {
+    Polargraph(Polargraph),
+    Cartesian(Cartesian),
+    CoreXY(CoreXY),
+}
+
+/// Calibration files written before other kinematics existed have no `kinematics` key and
+/// describe a polargraph. Files with the key must name one of the kinematics, so that a typo
+/// is reported instead of falling back to a polargraph.
+impl TryFrom<Map<String, Value>> for Machine {
+    type Error = serde_json::Error;
+
+    fn try_from(map: Map<String, Value>) -> Result<Self, Self::Error> {
+        if !map.contains_key("kinematics") {
+            return Ok(Machine::Polargraph(serde_json::from_value(Value::Object(
+                map,
+            ))?));
+        }
+
+        Ok(match serde_json::from_value(Value::Object(map))? {
+            TaggedMachine::Polargraph(m) => Machine::Polargraph(m),
+            TaggedMachine::Cartesian(m) => Machine::Cartesian(m),
+            TaggedMachine::CoreXY(m) => Machine::CoreXY(m),
+        })
+    }
+}
+
+fn y_axis(y: f64, invert_y: bool) -> f64 {
+    if invert_y {
+        -y
+    } else {
+        y
+    }
+}
+
+impl Kinematics for Polargraph {
+    fn apply(&self, pt: &Point) -> Point {
+        let pt = Point::new(pt.x, -pt.y);
+        let a =
+            ((0.5 * self.base_width + pt.x).powf(2.0) + (self.base_height - pt.y).powf(2.0)).sqrt();
+        let b =
+            ((0.5 * self.base_width - pt.x).powf(2.0) + (self.base_height - pt.y).powf(2.0)).sqrt();
+
+        Point::new(a, b)
+    }
+
+    fn invert(&self, pt: &Point) -> Point {
+        let (a, b) = (pt.x, pt.y);
+        let x = (a * a - b * b) / (2.0 * self.base_width);
+        let dy = (a * a - (0.5 * self.base_width + x).powf(2.0))
+            .max(0.0)
+            .sqrt();
+
+        Point::new(x, dy - self.base_height)
+    }
+
+    /// The belts lose tension when the pen comes close to the line between the motors or
+    /// moves outside of them.
+    fn limits(&self, margin: f64) -> (Point, Point) {
+        (
+            Point::new(-0.5 * self.base_width + margin, -self.base_height + margin),
+            Point::new(0.5 * self.base_width - margin, f64::INFINITY),
+        )
+    }
+}
+
+impl Kinematics for Cartesian {
+    fn apply(&self, pt: &Point) -> Point {
+        Point::new(pt.x, y_axis(pt.y, self.invert_y))
+    }
+
+    fn invert(&self, pt: &Point) -> Point {
+        Point::new(pt.x, y_axis(pt.y, self.invert_y))
+    }
+}
+
+impl Kinematics for CoreXY {
+    fn apply(&self, pt: &Point) -> Point {
+        let y = y_axis(pt.y, self.invert_y);
+        Point::new(pt.x + y, pt.x - y)
+    }
+
+    fn invert(&self, pt: &Point) -> Point {
+        let y = 0.5 * (pt.x - pt.y);
+        Point::new(0.5 * (pt.x + pt.y), y_axis(y, self.invert_y))
+    }
+}
+
+impl Kinematics for Machine {
+    fn apply(&self, pt: &Point) -> Point {
+        match self {
+            Machine::Polargraph(m) => m.apply(pt),
+            Machine::Cartesian(m) => m.apply(pt),
+            Machine::CoreXY(m) => m.apply(pt),
+        }
+    }
+
+    fn invert(&self, pt: &Point) -> Point {
+        match self {
+            Machine::Polargraph(m) => m.invert(pt),
+            Machine::Cartesian(m) => m.invert(pt),
+            Machine::CoreXY(m) => m.invert(pt),
+        }
+    }
+
+    fn limits(&self, margin: f64) -> (Point, Point) {
+        match self {
+            Machine::Polargraph(m) => m.limits(margin),
+            Machine::Cartesian(m) => m.limits(margin),
+            Machine::CoreXY(m) => m.limits(margin),
+        }
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Machine::Polargraph(m) => write!(
+                f,
+                "polargraph, base_width {}, base_height {}",
+                m.base_width, m.base_height
+            ),
+            Machine::Cartesian(m) => write!(f, "cartesian, invert_y {}", m.invert_y),
+            Machine::CoreXY(m) => write!(f, "corexy, invert_y {}", m.invert_y),
+        }
+    }
+}
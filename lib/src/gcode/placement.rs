@@ -0,0 +1,108 @@
+use crate::point::Point;
+use anyhow::{anyhow, Error};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Point of the drawing that is aligned with the same point of the drawing area.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    #[default]
+    Center,
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+/// How a drawing is scaled and positioned on the drawing area.
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Placement {
+    /// fixed scale in millimeters per pixel, if not set the
+    /// drawing is scaled to fill the drawing area while
+    /// preserving its ratio
+    #[serde(default)]
+    pub scale: Option<f64>,
+
+    #[serde(default)]
+    pub anchor: Anchor,
+
+    /// offset in millimeters added after anchoring
+    #[serde(default)]
+    pub offset: Point,
+}
+
+/// Uniform scale followed by a translation, shared by all channels of a drawing so that they
+/// stay registered on paper.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: f64,
+    pub translation: Point,
+}
+
+impl Anchor {
+    /// Position relative to the center in units of width and height.
+    fn factors(self) -> (f64, f64) {
+        match self {
+            Anchor::Center => (0.0, 0.0),
+            Anchor::TopLeft => (-0.5, -0.5),
+            Anchor::Top => (0.0, -0.5),
+            Anchor::TopRight => (0.5, -0.5),
+            Anchor::Left => (-0.5, 0.0),
+            Anchor::Right => (0.5, 0.0),
+            Anchor::BottomLeft => (-0.5, 0.5),
+            Anchor::Bottom => (0.0, 0.5),
+            Anchor::BottomRight => (0.5, 0.5),
+        }
+    }
+}
+
+impl FromStr for Anchor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "center" => Ok(Anchor::Center),
+            "top-left" => Ok(Anchor::TopLeft),
+            "top" => Ok(Anchor::Top),
+            "top-right" => Ok(Anchor::TopRight),
+            "left" => Ok(Anchor::Left),
+            "right" => Ok(Anchor::Right),
+            "bottom-left" => Ok(Anchor::BottomLeft),
+            "bottom" => Ok(Anchor::Bottom),
+            "bottom-right" => Ok(Anchor::BottomRight),
+            _ => Err(anyhow!("Unknown anchor `{}`", s)),
+        }
+    }
+}
+
+impl Placement {
+    /// Compute the transform mapping the bounding box from `min` to `max` onto a drawing area
+    /// of `width` and `height` centered at the origin.
+    pub fn transform(&self, min: Point, max: Point, width: f64, height: f64) -> Transform {
+        let size = max - min;
+        let fit = (width / size.x).min(height / size.y);
+        let scale = self
+            .scale
+            .unwrap_or(if fit.is_finite() { fit } else { 1.0 });
+
+        let (fx, fy) = self.anchor.factors();
+        let source = Point::new(min.x + (fx + 0.5) * size.x, min.y + (fy + 0.5) * size.y);
+        let target = Point::new(fx * width, fy * height) + self.offset;
+
+        Transform {
+            scale,
+            translation: target - source * scale,
+        }
+    }
+}
+
+impl Transform {
+    pub fn apply(&self, pt: &Point) -> Point {
+        *pt * self.scale + self.translation
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Calibration, Channel};
+use crate::point::Point;
+use std::time::Duration;
+
+/// Feed rates in millimeters per minute used to estimate the plotting time, moves with a feed
+/// rate that is not positive are not counted.
+#[derive(Copy, Clone, Debug)]
+pub struct Feedrates {
+    /// feed rate of drawing moves without an explicit `F` word
+    pub draw: f64,
+
+    /// feed rate of rapid travel moves
+    pub travel: f64,
+}
+
+/// Single linear move parsed from gcode in machine coordinates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Move {
+    /// true for `g1` moves with the pen on the paper, false for `g0` travel moves
+    pub draw: bool,
+    pub to: Point,
+    pub feed: Option<f64>,
+}
+
+/// Cartesian reconstruction of a gcode program.
+pub struct Preview {
+    /// drawn strokes in drawing coordinates
+    pub strokes: Vec<Channel>,
+
+    /// upper left corner of the area covered by strokes
+    pub min: Point,
+
+    /// lower right corner of the area covered by strokes
+    pub max: Point,
+
+    /// total length of all drawing moves in millimeters
+    pub draw_distance: f64,
+
+    /// total length of all travel moves in millimeters
+    pub travel_distance: f64,
+
+    /// time estimated from the machine space distances and feed rates
+    pub duration: Duration,
+}
+
+/// Split a line into its words, skipping comments and anything that is not a letter followed
+/// by a number such as the text of operator messages.
+pub(super) fn words(line: &str) -> Vec<(char, f64)> {
+    let line = line.split(';').next().unwrap_or_default();
+    let mut words = Vec::new();
+    let mut chars = line.chars().filter(|c| !c.is_whitespace()).peekable();
+
+    while let Some(letter) = chars.next() {
+        let mut number = String::new();
+
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.".contains(*c)) {
+            number.push(c);
+        }
+
+        if let Ok(value) = number.parse() {
+            words.push((letter.to_ascii_lowercase(), value));
+        }
+    }
+
+    words
+}
+
+/// Parse the `g0` and `g1` moves of a gcode program, ignoring everything else.
+pub fn parse(gcode: &str) -> Vec<Move> {
+    let mut moves = Vec::new();
+    let mut position = Point::origin();
+    let mut draw = None;
+    let mut feed = None;
+
+    for line in gcode.lines() {
+        let mut target = position;
+        let mut has_target = false;
+
+        for (letter, value) in words(line) {
+            match letter {
+                'g' if value == 0.0 => draw = Some(false),
+                'g' if value == 1.0 => draw = Some(true),
+                'x' => {
+                    target.x = value;
+                    has_target = true;
+                }
+                'y' => {
+                    target.y = value;
+                    has_target = true;
+                }
+                'f' => feed = Some(value),
+                _ => {}
+            }
+        }
+
+        if let (true, Some(draw)) = (has_target, draw) {
+            moves.push(Move {
+                draw,
+                to: target,
+                feed: if draw { feed } else { None },
+            });
+            position = target;
+        }
+    }
+
+    moves
+}
+
+impl Preview {
+    pub fn new(calibration: &Calibration, gcode: &str, feedrates: Feedrates) -> Self {
+        let mut strokes: Vec<Channel> = Vec::new();
+        let mut draw_distance = 0.0;
+        let mut travel_distance = 0.0;
+        let mut minutes = 0.0;
+        let mut machine = Point::origin();
+        let mut current = calibration.restore(&machine);
+        let mut drawing = false;
+
+        for m in parse(gcode) {
+            let next = calibration.restore(&m.to);
+            let distance = current.distance(&next);
+            let feed = if m.draw {
+                m.feed.unwrap_or(feedrates.draw)
+            } else {
+                feedrates.travel
+            };
+
+            // Controllers reject moves without a positive feed, so they take no time.
+            if feed.is_finite() && feed > 0.0 {
+                minutes += machine.distance(&m.to) / feed;
+            }
+
+            if m.draw {
+                if !drawing {
+                    strokes.push(vec![current]);
+                    drawing = true;
+                }
+
+                strokes.last_mut().unwrap().push(next);
+                draw_distance += distance;
+            } else {
+                drawing = false;
+                travel_distance += distance;
+            }
+
+            machine = m.to;
+            current = next;
+        }
+
+        let mut points = strokes.iter().flatten();
+        let first = points.next().copied().unwrap_or_else(Point::origin);
+        let (min, max) = points.fold((first, first), |(min, max), p| {
+            (
+                Point::new(min.x.min(p.x), min.y.min(p.y)),
+                Point::new(max.x.max(p.x), max.y.max(p.y)),
+            )
+        });
+
+        Self {
+            strokes,
+            min,
+            max,
+            draw_distance,
+            travel_distance,
+            duration: Duration::try_from_secs_f64(minutes * 60.0).unwrap_or(Duration::MAX),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_without_feed_take_no_time() {
+        let calib: Calibration = serde_json::from_str(
+            r#"{"kinematics": "cartesian", "drawing_width": 10, "drawing_height": 10}"#,
+        )
+        .unwrap();
+        let feedrates = Feedrates {
+            draw: 60.,
+            travel: 60.,
+        };
+
+        let preview = Preview::new(&calib, "G1 X10 Y0 F0\nG1 X10 Y10 F-5\nG0 X0 Y10", feedrates);
+        assert_eq!(preview.draw_distance, 20.);
+        assert_eq!(preview.duration, Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,145 @@
+use super::preview::{parse, words};
+use super::sender::is_program_pause;
+use super::Stroke;
+use crate::point::Point;
+
+/// Position at which an interrupted plot is continued.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Start {
+    /// index of the first stroke or gcode line to draw
+    Index(usize),
+
+    /// percentage of the drawn length or of the gcode lines already done
+    Percent(f64),
+}
+
+/// Drop everything before `start` from `strokes`, splitting the stroke in which a percentage of
+/// the drawn length is reached.
+pub fn resume_strokes(strokes: &[Stroke], start: Start) -> Vec<Stroke> {
+    let percent = match start {
+        Start::Index(index) => return strokes.iter().skip(index).cloned().collect(),
+        Start::Percent(percent) => percent.clamp(0.0, 100.0),
+    };
+
+    let length = |stroke: &Stroke| -> f64 {
+        stroke
+            .windows(2)
+            .map(|points| points[0].distance(&points[1]))
+            .sum()
+    };
+
+    let mut remaining = strokes.iter().map(length).sum::<f64>() * percent / 100.0;
+    let mut result = Vec::new();
+
+    for stroke in strokes {
+        let stroke_length = length(stroke);
+
+        if remaining <= 0.0 {
+            result.push(stroke.clone());
+        } else if remaining < stroke_length {
+            for (index, segment) in stroke.windows(2).enumerate() {
+                let distance = segment[0].distance(&segment[1]);
+
+                if remaining < distance {
+                    let split = segment[0] + (segment[1] - segment[0]) * (remaining / distance);
+                    let mut resumed = vec![split];
+                    resumed.extend_from_slice(&stroke[index + 1..]);
+                    result.push(resumed);
+                    break;
+                }
+
+                remaining -= distance;
+            }
+        }
+
+        remaining -= stroke_length;
+    }
+
+    result
+}
+
+impl Start {
+    /// Index of the first line to send of a program with `total` lines.
+    pub fn line(self, total: usize) -> usize {
+        match self {
+            Start::Index(index) => index.min(total),
+            Start::Percent(percent) => {
+                (total as f64 * percent.clamp(0.0, 100.0) / 100.0).round() as usize
+            }
+        }
+    }
+}
+
+/// Continue a gcode program at line `start` of `lines`.
+///
+/// The setup lines before the first move except for program pauses are kept, then the pen is
+/// lifted and moved to the position the machine would have reached before the resumed line and
+/// lowered again if that line draws.
+pub fn resume_program(
+    lines: &[&str],
+    start: usize,
+    pen_up: Option<&str>,
+    pen_down: Option<&str>,
+) -> Vec<String> {
+    let has_axes = |line: &&&str| words(line).iter().any(|(l, _)| *l == 'x' || *l == 'y');
+    let done = parse(&lines[..start].join("\n"));
+    let position = done.last().map(|m| m.to).unwrap_or_else(Point::origin);
+    let feed = done.iter().rev().find_map(|m| m.feed);
+    let draws = parse(&lines[start..].join("\n"))
+        .first()
+        .map(|m| m.draw)
+        .unwrap_or(false);
+
+    let mut result = lines[..start]
+        .iter()
+        .take_while(|line| !has_axes(line))
+        .filter(|line| !is_program_pause(line))
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+
+    result.extend(pen_up.map(str::to_string));
+    result.push(format!("g0 x{} y{}", position.x, position.y));
+
+    if draws {
+        result.extend(pen_down.map(str::to_string));
+    }
+
+    if let Some(feed) = feed {
+        result.push(format!("g1 f{}", feed));
+    }
+
+    result.extend(lines[start..].iter().map(|line| line.to_string()));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resuming_strokes_at_percentage_works() {
+        let strokes = vec![
+            vec![Point::new(0., 0.), Point::new(2., 0.)],
+            vec![Point::new(0., 1.), Point::new(4., 1.), Point::new(4., 3.)],
+        ];
+
+        assert_eq!(
+            resume_strokes(&strokes, Start::Percent(50.)),
+            vec![vec![
+                Point::new(2., 1.),
+                Point::new(4., 1.),
+                Point::new(4., 3.)
+            ]]
+        );
+    }
+
+    #[test]
+    fn resuming_programs_travels_with_pen_up() {
+        let lines = ["g21", "g0 x1 y1", "M3 S30", "g1 x2 y2 f500", "g1 x3 y3"];
+
+        assert_eq!(
+            resume_program(&lines, 4, Some("M3 S90"), Some("M3 S30")),
+            vec!["g21", "M3 S90", "g0 x2 y2", "M3 S30", "g1 f500", "g1 x3 y3"]
+        );
+    }
+}
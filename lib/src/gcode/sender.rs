@@ -0,0 +1,358 @@
+use super::preview::words;
+use anyhow::{anyhow, bail, Error, Result};
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Size of the serial receive buffer of GRBL.
+pub const GRBL_RX_BUFFER_SIZE: usize = 128;
+
+/// Flow control used to stream lines to the controller.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protocol {
+    /// Keep the receive buffer of the controller filled by counting the characters of all lines
+    /// that have not been acknowledged yet.
+    CharacterCounting,
+
+    /// Send the next line only after the previous one has been acknowledged with `ok`.
+    SendResponse,
+}
+
+const NONE: u8 = 0;
+const PAUSE: u8 = 1;
+const RESUME: u8 = 2;
+const ABORT: u8 = 3;
+
+/// Notification about the progress of a [`Sender`].
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    /// the controller acknowledged `line` of `total` program lines
+    Acknowledged { line: usize, total: usize },
+
+    /// the controller reached an `M0` or `M1` program pause, i.e. acknowledged all lines before
+    /// it, including its comment such as `insert cyan pen`, and streaming continues after
+    /// [`Control::resume`]
+    ProgramPause(&'a str),
+}
+
+/// Handle to pause, resume or abort a running [`Sender`] from another thread.
+#[derive(Clone, Default)]
+pub struct Control {
+    request: Arc<AtomicU8>,
+}
+
+/// Streams gcode to a GRBL compatible controller connected via `stream`.
+pub struct Sender<S> {
+    stream: S,
+    protocol: Protocol,
+    buffer_size: usize,
+    control: Control,
+    received: Vec<u8>,
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "character-counting" => Ok(Protocol::CharacterCounting),
+            "send-response" => Ok(Protocol::SendResponse),
+            _ => Err(anyhow!("Unknown protocol `{}`", s)),
+        }
+    }
+}
+
+impl Control {
+    /// Hold the feed and stop sending further lines.
+    pub fn pause(&self) {
+        self.request.store(PAUSE, Ordering::SeqCst);
+    }
+
+    /// Continue a paused stream.
+    pub fn resume(&self) {
+        self.request.store(RESUME, Ordering::SeqCst);
+    }
+
+    /// Reset the controller and stop streaming with an error.
+    pub fn abort(&self) {
+        self.request.store(ABORT, Ordering::SeqCst);
+    }
+
+    fn take(&self) -> u8 {
+        self.request.swap(NONE, Ordering::SeqCst)
+    }
+}
+
+/// Remove comments and whitespace that the controller does not need to see.
+fn clean(line: &str) -> &str {
+    line.split(';').next().unwrap_or_default().trim()
+}
+
+/// Lines of `gcode` that are actually sent, i.e. without comments and empty lines.
+pub fn program_lines(gcode: &str) -> Vec<&str> {
+    gcode.lines().map(clean).filter(|l| !l.is_empty()).collect()
+}
+
+/// Check if `line` is an `M0` or `M1` after which GRBL waits for a cycle start.
+pub fn is_program_pause(line: &str) -> bool {
+    matches!(words(line).first(), Some(('m', code)) if *code == 0.0 || *code == 1.0)
+}
+
+impl<S: Read + Write> Sender<S> {
+    pub fn new(stream: S, protocol: Protocol) -> Self {
+        Self {
+            stream,
+            protocol,
+            buffer_size: GRBL_RX_BUFFER_SIZE,
+            control: Control::default(),
+            received: Vec::new(),
+        }
+    }
+
+    /// Use a receive buffer size different from GRBL's for character counting.
+    pub fn with_buffer_size(self, buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            ..self
+        }
+    }
+
+    pub fn control(&self) -> Control {
+        self.control.clone()
+    }
+
+    /// Read a single response line, returning `None` if the stream timed out before a line was
+    /// complete.
+    fn poll_response(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(end) = self.received.iter().position(|b| *b == b'\n') {
+                let line = self.received.drain(..=end).collect::<Vec<_>>();
+                return Ok(Some(String::from_utf8_lossy(&line).trim().to_string()));
+            }
+
+            let mut buffer = [0; 64];
+
+            match self.stream.read(&mut buffer) {
+                Ok(0) => bail!("Connection to controller closed"),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock | ErrorKind::Interrupted
+                    ) =>
+                {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Stream all lines of `gcode` and wait until the controller acknowledged them. `notify` is
+    /// called for each [`Event`], counting lines as [`program_lines`] does.
+    pub fn send<F: FnMut(Event)>(&mut self, gcode: &str, mut notify: F) -> Result<()> {
+        let messages = gcode
+            .lines()
+            .filter(|l| !clean(l).is_empty())
+            .map(str::trim)
+            .collect::<Vec<_>>();
+        let lines = program_lines(gcode);
+
+        let mut in_flight = VecDeque::new();
+        let mut sent = 0;
+        let mut acknowledged = 0;
+        let mut paused = false;
+
+        // Program pause that was sent while the lines before it are still being executed.
+        let mut pending_pause = None;
+
+        while acknowledged < lines.len() {
+            if let Some(index) = pending_pause.filter(|index| acknowledged >= *index) {
+                notify(Event::ProgramPause(messages[index]));
+                pending_pause = None;
+                paused = true;
+            }
+
+            match self.control.take() {
+                PAUSE if !paused => {
+                    info!("Pausing");
+                    self.stream.write_all(b"!")?;
+                    paused = true;
+                }
+                RESUME if paused => {
+                    info!("Resuming");
+                    self.stream.write_all(b"~")?;
+                    paused = false;
+                }
+                ABORT => {
+                    self.stream.write_all(&[0x18])?;
+                    bail!("Aborted after {} of {} lines", acknowledged, lines.len());
+                }
+                _ => {}
+            }
+
+            if !paused && pending_pause.is_none() && sent < lines.len() {
+                let length = lines[sent].len() + 1;
+                let fits = match self.protocol {
+                    Protocol::CharacterCounting => {
+                        in_flight.iter().sum::<usize>() + length <= self.buffer_size
+                    }
+                    Protocol::SendResponse => in_flight.is_empty(),
+                };
+
+                if fits || in_flight.is_empty() {
+                    self.stream.write_all(lines[sent].as_bytes())?;
+                    self.stream.write_all(b"\n")?;
+                    self.stream.flush()?;
+                    in_flight.push_back(length);
+
+                    if is_program_pause(lines[sent]) {
+                        pending_pause = Some(sent);
+                    }
+
+                    sent += 1;
+                    continue;
+                }
+            }
+
+            if in_flight.is_empty() {
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+
+            match self.poll_response()? {
+                Some(response) if response == "ok" => {
+                    in_flight.pop_front();
+                    acknowledged += 1;
+                    notify(Event::Acknowledged {
+                        line: acknowledged,
+                        total: lines.len(),
+                    });
+                }
+                Some(response)
+                    if response.starts_with("error") || response.starts_with("ALARM") =>
+                {
+                    bail!(
+                        "Line {} `{}` failed with `{}`",
+                        acknowledged + 1,
+                        lines[acknowledged],
+                        response
+                    );
+                }
+                Some(response) => debug!("Controller: {}", response),
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Controller that executes one buffered line for every read and checks that its receive
+    /// buffer never overflows.
+    struct FakeGrbl {
+        rx: Vec<u8>,
+        tx: Vec<u8>,
+        executed: Vec<String>,
+    }
+
+    impl Read for FakeGrbl {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if let Some(end) = self.rx.iter().position(|b| *b == b'\n') {
+                let line = String::from_utf8(self.rx.drain(..=end).collect()).unwrap();
+                let line = line.trim().to_string();
+                let response = if line.contains("bad") {
+                    "error:20\n"
+                } else {
+                    "ok\n"
+                };
+                self.tx.extend_from_slice(response.as_bytes());
+                self.executed.push(line);
+            }
+
+            let n = self.tx.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.tx[..n]);
+            self.tx.drain(..n);
+            Ok(n)
+        }
+    }
+
+    impl Write for FakeGrbl {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.rx.extend_from_slice(buf);
+            assert!(self.rx.len() <= GRBL_RX_BUFFER_SIZE);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn fake() -> FakeGrbl {
+        FakeGrbl {
+            rx: Vec::new(),
+            tx: Vec::new(),
+            executed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn streaming_works() {
+        let gcode = (0..100)
+            .map(|i| format!("g1 x{} y{} ; comment", i, 100 - i))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        for protocol in [Protocol::CharacterCounting, Protocol::SendResponse] {
+            let mut sender = Sender::new(fake(), protocol);
+            let mut acknowledged = 0;
+
+            sender
+                .send(&gcode, |event| {
+                    if let Event::Acknowledged { line, .. } = event {
+                        acknowledged = line;
+                    }
+                })
+                .unwrap();
+            assert_eq!(acknowledged, 100);
+            assert_eq!(sender.stream.executed[99], "g1 x99 y1");
+        }
+    }
+
+    #[test]
+    fn program_pauses_wait_for_previous_lines() {
+        let mut sender = Sender::new(fake(), Protocol::CharacterCounting);
+        let control = sender.control();
+        let mut events = Vec::new();
+
+        sender
+            .send("g0 x1\ng0 x2\nM0 ; insert cyan pen\ng0 x3", |event| {
+                if let Event::ProgramPause(message) = event {
+                    control.resume();
+                    events.push(message.to_string());
+                } else if let Event::Acknowledged { line, .. } = event {
+                    events.push(line.to_string());
+                }
+            })
+            .unwrap();
+
+        assert_eq!(events, ["1", "2", "M0 ; insert cyan pen", "3", "4"]);
+    }
+
+    #[test]
+    fn errors_stop_streaming() {
+        let mut sender = Sender::new(fake(), Protocol::CharacterCounting);
+        let result = sender.send("g0 x1\nbad\ng0 x2", |_| {});
+
+        assert!(result.unwrap_err().to_string().starts_with("Line 2 `bad`"));
+    }
+}
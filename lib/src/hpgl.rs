@@ -0,0 +1,274 @@
+use anyhow::{anyhow, Error, Result};
+
+use crate::Point;
+use std::fmt::Write as _;
+use std::path;
+use std::str::FromStr;
+
+/// Plotter units per millimeter.
+pub const UNITS_PER_MM: f64 = 40.0;
+
+/// Maximum number of coordinate pairs per `PD` instruction to keep within small plotter buffers.
+const MAX_COORDINATES: usize = 32;
+
+/// Number of pens in the carousel of an HP 7475A.
+pub const PEN_SLOTS: u8 = 6;
+
+/// Paper sizes with the hard clip limits of an HP 7475A in landscape orientation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PageSize {
+    A4,
+    A3,
+    Letter,
+    Tabloid,
+}
+
+impl PageSize {
+    /// Width and height of the plotting area in plotter units.
+    pub fn units(&self) -> (f64, f64) {
+        match self {
+            PageSize::A4 => (10900.0, 7650.0),
+            PageSize::A3 => (16640.0, 10900.0),
+            PageSize::Letter => (10365.0, 7962.0),
+            PageSize::Tabloid => (16640.0, 10365.0),
+        }
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a4" => Ok(PageSize::A4),
+            "a3" => Ok(PageSize::A3),
+            "letter" => Ok(PageSize::Letter),
+            "tabloid" => Ok(PageSize::Tabloid),
+            _ => Err(anyhow!("Unknown page size `{}`", s)),
+        }
+    }
+}
+
+/// Maps image coordinates onto the page, scaled to fit, centered and rotated by 90 degrees if
+/// that yields a larger drawing. The y axis is flipped because HPGL has its origin at the lower
+/// left corner.
+struct Mapping {
+    scale: f64,
+    rotate: bool,
+    offset: (f64, f64),
+    height: f64,
+}
+
+impl Mapping {
+    fn new(width: u32, height: u32, page: PageSize) -> Self {
+        let (page_width, page_height) = page.units();
+        let (width, height) = (width as f64, height as f64);
+        let fit = |w: f64, h: f64| (page_width / w).min(page_height / h);
+        let rotate = fit(height, width) > fit(width, height);
+
+        let (w, h) = if rotate {
+            (height, width)
+        } else {
+            (width, height)
+        };
+
+        let scale = fit(w, h);
+
+        Self {
+            scale,
+            rotate,
+            offset: (
+                0.5 * (page_width - w * scale),
+                0.5 * (page_height - h * scale),
+            ),
+            height,
+        }
+    }
+
+    fn apply(&self, p: &Point) -> (i32, i32) {
+        let (x, y) = if self.rotate {
+            (p.y, p.x)
+        } else {
+            (p.x, self.height - p.y)
+        };
+
+        (
+            (x * self.scale + self.offset.0).round() as i32,
+            (y * self.scale + self.offset.1).round() as i32,
+        )
+    }
+}
+
+/// Pen slot of each channel, either given explicitly or consecutive slots for all non-empty
+/// channels.
+fn pen_slots(point_sets: &[Vec<Point>], pens: Option<&[u8]>) -> Result<Vec<u8>> {
+    let slots = match pens {
+        Some(pens) if pens.len() < point_sets.len() => {
+            return Err(anyhow!(
+                "{} pens given for {} channels",
+                pens.len(),
+                point_sets.len()
+            ))
+        }
+        Some(pens) => pens.to_vec(),
+        None => {
+            let mut slot = 0;
+
+            point_sets
+                .iter()
+                .map(|points| {
+                    if !points.is_empty() {
+                        slot += 1;
+                    }
+                    slot
+                })
+                .collect()
+        }
+    };
+
+    let used = slots.iter().zip(point_sets).filter(|(_, p)| !p.is_empty());
+
+    if let Some((slot, _)) = used
+        .into_iter()
+        .find(|(slot, _)| !(1..=PEN_SLOTS).contains(*slot))
+    {
+        return Err(anyhow!(
+            "Pen slot {} does not exist, the carousel has slots 1 to {}",
+            slot,
+            PEN_SLOTS
+        ));
+    }
+
+    Ok(slots)
+}
+
+fn render(
+    point_sets: &[Vec<Point>],
+    width: u32,
+    height: u32,
+    page: PageSize,
+    pens: Option<&[u8]>,
+    draw: impl Fn(&mut String, &[(i32, i32)]),
+) -> Result<String> {
+    let mapping = Mapping::new(width, height, page);
+    let mut hpgl = String::from("IN;");
+
+    for (points, pen) in point_sets.iter().zip(pen_slots(point_sets, pens)?) {
+        if points.is_empty() {
+            continue;
+        }
+
+        let coordinates = points.iter().map(|p| mapping.apply(p)).collect::<Vec<_>>();
+        write!(hpgl, "SP{};", pen)?;
+        draw(&mut hpgl, &coordinates);
+    }
+
+    hpgl.push_str("PU;SP0;\n");
+
+    Ok(hpgl)
+}
+
+fn draw_path(hpgl: &mut String, coordinates: &[(i32, i32)]) {
+    let (x, y) = coordinates[0];
+    write!(hpgl, "PU{},{};", x, y).unwrap();
+
+    for chunk in coordinates[1..].chunks(MAX_COORDINATES) {
+        let pairs = chunk
+            .iter()
+            .map(|(x, y)| format!("{},{}", x, y))
+            .collect::<Vec<_>>();
+
+        write!(hpgl, "PD{};", pairs.join(",")).unwrap();
+    }
+
+    hpgl.push_str("PU;\n");
+}
+
+fn draw_points(hpgl: &mut String, coordinates: &[(i32, i32)]) {
+    for (x, y) in coordinates {
+        write!(hpgl, "PU{},{};PD;PU;", x, y).unwrap();
+    }
+
+    hpgl.push('\n');
+}
+
+/// Write each channel as one continuous path with the pen of the corresponding slot.
+pub fn write_path(
+    filename: &path::Path,
+    point_sets: &[Vec<Point>],
+    width: u32,
+    height: u32,
+    page: PageSize,
+    pens: Option<&[u8]>,
+) -> Result<()> {
+    let hpgl = render(point_sets, width, height, page, pens, draw_path)?;
+    Ok(std::fs::write(filename, hpgl)?)
+}
+
+/// Write each point as a dot with the pen of the corresponding slot.
+pub fn write_points(
+    filename: &path::Path,
+    point_sets: &[Vec<Point>],
+    width: u32,
+    height: u32,
+    page: PageSize,
+    pens: Option<&[u8]>,
+) -> Result<()> {
+    let hpgl = render(point_sets, width, height, page, pens, draw_points)?;
+    Ok(std::fs::write(filename, hpgl)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_is_flipped_and_centered() {
+        let points = vec![
+            Point::new(0., 0.),
+            Point::new(50., 25.),
+            Point::new(100., 50.),
+        ];
+        let hpgl = render(&[points], 100, 50, PageSize::A4, None, draw_path).unwrap();
+
+        assert_eq!(
+            hpgl,
+            "IN;SP1;PU0,6550;PD5450,3825,10900,1100;PU;\nPU;SP0;\n"
+        );
+    }
+
+    #[test]
+    fn portrait_images_are_rotated() {
+        let points = vec![Point::new(0., 0.), Point::new(50., 100.)];
+        let hpgl = render(&[points], 50, 100, PageSize::A4, None, draw_points).unwrap();
+
+        assert_eq!(hpgl, "IN;SP1;PU0,1100;PD;PU;PU10900,6550;PD;PU;\nPU;SP0;\n");
+    }
+
+    #[test]
+    fn long_paths_are_chunked() {
+        let points = (0..34)
+            .map(|i| Point::new(i as f64, 0.))
+            .collect::<Vec<_>>();
+        let hpgl = render(&[points], 100, 50, PageSize::A4, None, draw_path).unwrap();
+        let instructions = hpgl.split(';').collect::<Vec<_>>();
+
+        assert_eq!(
+            instructions.iter().filter(|i| i.starts_with("PD")).count(),
+            2
+        );
+        assert_eq!(instructions[3].split(',').count(), 2 * MAX_COORDINATES);
+        assert_eq!(instructions[4].split(',').count(), 2);
+    }
+
+    #[test]
+    fn pens_fit_into_carousel() {
+        let point = vec![Point::new(0., 0.)];
+        let sets = vec![point.clone(), Vec::new(), point.clone()];
+        assert_eq!(pen_slots(&sets, None).unwrap(), [1, 1, 2]);
+        assert_eq!(pen_slots(&sets, Some(&[4, 9, 6])).unwrap(), [4, 9, 6]);
+        assert!(pen_slots(&sets, Some(&[7, 1, 2])).is_err());
+        assert!(pen_slots(&sets, Some(&[1, 2])).is_err());
+        assert!(pen_slots(&vec![point; 7], None).is_err());
+    }
+}
@@ -1,51 +1,241 @@
 pub mod color;
+pub mod document;
 #[cfg(feature = "gcode")]
 pub mod gcode;
+#[cfg(feature = "hpgl")]
+pub mod hpgl;
 pub mod point;
+pub mod preprocess;
+pub mod screen;
 #[cfg(feature = "svg")]
 pub mod svg;
 pub mod tsp;
 pub mod voronoi;
 
+use anyhow::{bail, Result};
 use image::GenericImageView;
 use rand::Rng;
 
 pub use point::Point;
 
+/// Random positions tried for each point of all budgets before giving up on channels that are
+/// too faint to ever meet their budget.
+const MAX_ATTEMPTS_PER_POINT: usize = 1000;
+
+/// Number of points and gamma of a single channel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Budget {
+    pub num_points: usize,
+    pub gamma: f32,
+}
+
 pub fn sample_points(
     img: &image::DynamicImage,
     num_points: usize,
     gamma: f32,
     cmyk: bool,
 ) -> Vec<Vec<Point>> {
+    if cmyk {
+        return sample_cmyk(img, num_points, gamma, &color::Separation::default());
+    }
+
+    sample_channels(img, num_points, &[gamma], |r, g, b| {
+        vec![0.0, 0.0, 0.0, color::to_black(r, g, b)]
+    })
+}
+
+/// Sample points of the cyan, magenta, yellow and black channels of a print separated with
+/// `separation`.
+pub fn sample_cmyk(
+    img: &image::DynamicImage,
+    num_points: usize,
+    gamma: f32,
+    separation: &color::Separation,
+) -> Vec<Vec<Point>> {
+    sample_channels(img, num_points, &[gamma], |r, g, b| {
+        let (c, m, y, k) = separation.separate(r, g, b);
+        vec![c, m, y, k]
+    })
+}
+
+/// Sample points of as many channels as `densities` returns for the color of each pixel, with
+/// 1 being the darkest. `gammas` holds the gamma of each channel or a single one for all.
+pub fn sample_channels<F>(
+    img: &image::DynamicImage,
+    num_points: usize,
+    gammas: &[f32],
+    mut densities: F,
+) -> Vec<Vec<Point>>
+where
+    F: FnMut(u8, u8, u8) -> Vec<f32>,
+{
     let (width, height) = img.dimensions();
     let mut rng = rand::thread_rng();
-
-    // Store points for each channel
-    let mut ps = vec![Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+    let mut ps: Vec<Vec<Point>> = Vec::new();
 
     while ps.iter().map(|points| points.len()).sum::<usize>() < num_points {
         let x = rng.gen::<f64>() * width as f64;
         let y = rng.gen::<f64>() * height as f64;
         let channels = img.get_pixel(x as u32, y as u32);
         let sample: f32 = rng.gen();
+        let densities = densities(channels[0], channels[1], channels[2]);
 
-        if cmyk {
-            let cmyk = color::invert(color::to_cmyk(channels[0], channels[1], channels[2]));
+        // Store points for each channel
+        ps.resize_with(densities.len(), Vec::new);
 
-            for (points, color) in ps.iter_mut().zip(cmyk.iter()) {
-                if sample >= color.powf(gamma) {
-                    points.push(Point::new(x, y));
-                }
-            }
-        } else {
-            let black = 1.0 - color::to_black(channels[0], channels[1], channels[2]);
+        for (index, (points, density)) in ps.iter_mut().zip(densities).enumerate() {
+            let gamma = gammas.get(index).unwrap_or(&gammas[0]);
 
-            if sample >= black.powf(gamma) {
-                ps[3].push(Point::new(x, y));
+            if sample >= (1.0 - density).powf(*gamma) {
+                points.push(Point::new(x, y));
             }
         }
     }
 
     ps
 }
+
+/// Sample exactly the number of points of each of `budgets` from the channels of `densities`,
+/// with the gamma of each channel. Channels that are empty everywhere get no points and
+/// channels without a budget are ignored. Fails if a channel is too faint to meet its budget
+/// within a bounded number of attempts.
+pub fn sample_budgets<F>(
+    img: &image::DynamicImage,
+    budgets: &[Budget],
+    mut densities: F,
+) -> Result<Vec<Vec<Point>>>
+where
+    F: FnMut(u8, u8, u8) -> Vec<f32>,
+{
+    let (width, height) = img.dimensions();
+    let mut rng = rand::thread_rng();
+    let mut ps: Vec<Vec<Point>> = vec![Vec::new(); budgets.len()];
+
+    // Only look for points where they can be found, otherwise sampling would never stop.
+    let mut reachable = vec![false; budgets.len()];
+
+    for (_, _, pixel) in img.pixels() {
+        for (found, density) in reachable
+            .iter_mut()
+            .zip(densities(pixel[0], pixel[1], pixel[2]))
+        {
+            *found |= density > 0.0;
+        }
+
+        if reachable.iter().all(|found| *found) {
+            break;
+        }
+    }
+
+    let complete = |ps: &[Vec<Point>]| {
+        ps.iter()
+            .zip(budgets)
+            .zip(&reachable)
+            .all(|((points, budget), reachable)| !reachable || points.len() >= budget.num_points)
+    };
+
+    let max_attempts = MAX_ATTEMPTS_PER_POINT * budgets.iter().map(|b| b.num_points).sum::<usize>();
+    let mut attempts = 0;
+
+    while !complete(&ps) {
+        if attempts == max_attempts {
+            let index = (0..budgets.len())
+                .find(|i| reachable[*i] && ps[*i].len() < budgets[*i].num_points)
+                .unwrap_or_default();
+
+            bail!(
+                "Channel {} is too faint for {} points, only {} were found",
+                index,
+                budgets[index].num_points,
+                ps[index].len()
+            );
+        }
+
+        attempts += 1;
+        let x = rng.gen::<f64>() * width as f64;
+        let y = rng.gen::<f64>() * height as f64;
+        let channels = img.get_pixel(x as u32, y as u32);
+        let sample: f32 = rng.gen();
+        let densities = densities(channels[0], channels[1], channels[2]);
+
+        for ((points, budget), density) in ps.iter_mut().zip(budgets).zip(densities) {
+            if points.len() < budget.num_points && sample >= (1.0 - density).powf(budget.gamma) {
+                points.push(Point::new(x, y));
+            }
+        }
+    }
+
+    Ok(ps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_have_own_gamma() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::new(4, 4));
+        let ps = sample_channels(&img, 2000, &[1.0, 4.0], |_, _, _| vec![0.5, 0.5]);
+
+        // Points are kept above (1 - 0.5)^gamma, so about half of them in the first channel
+        // and almost all of them in the second.
+        assert!(ps[1].len() > ps[0].len() * 3 / 2);
+    }
+
+    #[test]
+    fn faint_channels_do_not_block_sampling() {
+        let mut img = image::RgbImage::from_pixel(100, 100, image::Rgb([0, 0, 0]));
+        img.put_pixel(50, 50, image::Rgb([0, 0, 10]));
+
+        let budgets = [
+            Budget {
+                num_points: 100,
+                gamma: 1.0,
+            },
+            Budget {
+                num_points: 100,
+                gamma: 1.0,
+            },
+        ];
+        let result = sample_budgets(&image::DynamicImage::ImageRgb8(img), &budgets, |r, _, b| {
+            vec![1.0 - r as f32 / 255.0, b as f32 / 255.0]
+        });
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Channel 1 is too faint"));
+    }
+
+    #[test]
+    fn budgets_are_met() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            4,
+            4,
+            image::Rgb([255, 0, 255]),
+        ));
+        let budgets = [
+            Budget {
+                num_points: 100,
+                gamma: 1.0,
+            },
+            Budget {
+                num_points: 10,
+                gamma: 2.0,
+            },
+            Budget {
+                num_points: 50,
+                gamma: 1.0,
+            },
+        ];
+        let separation = color::Separation::default();
+        let ps = sample_budgets(&img, &budgets, |r, g, b| {
+            let (c, m, y, k) = separation.separate(r, g, b);
+            vec![c, m, y, k]
+        })
+        .unwrap();
+
+        let counts: Vec<_> = ps.iter().map(|points| points.len()).collect();
+        assert_eq!(counts, [0, 10, 0]);
+    }
+}
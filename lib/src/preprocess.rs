@@ -0,0 +1,553 @@
+use anyhow::{anyhow, bail, Error, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::str::FromStr;
+
+/// Clockwise rotation by a multiple of 90 degrees.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rotation {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+}
+
+/// Changes of the size and orientation of an image applied before anything else, so that point
+/// coordinates refer to the resulting image.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Geometry {
+    /// left, top, width and height of the part of the source image to keep
+    pub crop: Option<(u32, u32, u32, u32)>,
+
+    pub rotate: Option<Rotation>,
+
+    /// mirror left and right
+    pub flip_horizontal: bool,
+
+    /// mirror top and bottom
+    pub flip_vertical: bool,
+
+    /// width and height the image is scaled to fit into, keeping its aspect ratio
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Tonal corrections and filters applied to an image before points are sampled from it.
+///
+/// Transparent parts of the image are always composed onto the color of the paper, and fully
+/// transparent parts as well as the removed background keep that color regardless of the other
+/// adjustments so that they receive no points. Grayscale or 16 bit images are converted to 8 bit
+/// RGB.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adjustments {
+    /// color of the paper, white unless light pens are drawn on black paper
+    pub paper: [u8; 3],
+
+    /// maximum difference of a channel to the color of the border for a pixel connected to the
+    /// border to be replaced with the color of the paper
+    pub remove_background: Option<u8>,
+
+    /// input value mapped to black
+    pub black_point: u8,
+
+    /// input value mapped to white
+    pub white_point: u8,
+
+    /// spread the values evenly over the whole range
+    pub equalize: bool,
+
+    /// value added to all channels, negative values darken
+    pub brightness: i32,
+
+    /// change of contrast in percent, negative values reduce it
+    pub contrast: f32,
+
+    /// standard deviation of a Gaussian blur in pixels
+    pub blur: Option<f32>,
+
+    /// standard deviation and threshold of an unsharp mask
+    pub unsharpen: Option<(f32, i32)>,
+
+    /// weight of the Sobel edge magnitude added to the darkness, so that more points are placed
+    /// along contours
+    pub edges: f32,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            paper: [255, 255, 255],
+            remove_background: None,
+            black_point: 0,
+            white_point: 255,
+            equalize: false,
+            brightness: 0,
+            contrast: 0.0,
+            blur: None,
+            unsharpen: None,
+            edges: 0.0,
+        }
+    }
+}
+
+impl FromStr for Rotation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "90" => Ok(Rotation::Rotate90),
+            "180" => Ok(Rotation::Rotate180),
+            "270" => Ok(Rotation::Rotate270),
+            _ => Err(anyhow!("Unsupported rotation `{}`, use 90, 180 or 270", s)),
+        }
+    }
+}
+
+impl Geometry {
+    pub fn apply(&self, img: DynamicImage) -> Result<DynamicImage> {
+        let mut img = img;
+
+        if let Some((x, y, width, height)) = self.crop {
+            let (image_width, image_height) = img.dimensions();
+
+            if x >= image_width || y >= image_height || width == 0 || height == 0 {
+                bail!(
+                    "Crop rectangle lies outside of the {}x{} image",
+                    image_width,
+                    image_height
+                );
+            }
+
+            img = img.crop_imm(x, y, width, height);
+        }
+
+        img = match self.rotate {
+            Some(Rotation::Rotate90) => img.rotate90(),
+            Some(Rotation::Rotate180) => img.rotate180(),
+            Some(Rotation::Rotate270) => img.rotate270(),
+            None => img,
+        };
+
+        if self.flip_horizontal {
+            img = img.fliph();
+        }
+
+        if self.flip_vertical {
+            img = img.flipv();
+        }
+
+        if self.width.is_some() || self.height.is_some() {
+            img = img.resize(
+                self.width.unwrap_or(u32::MAX),
+                self.height.unwrap_or(u32::MAX),
+                FilterType::CatmullRom,
+            );
+        }
+
+        Ok(img)
+    }
+}
+
+/// Scale the color of `pixel` so that its value, i.e. its brightest channel, becomes `value`,
+/// keeping hue, saturation and alpha.
+fn set_value(pixel: &mut Rgba<u8>, value: f32) {
+    let current = pixel[0].max(pixel[1]).max(pixel[2]);
+
+    if current == 0 {
+        let value = value.round() as u8;
+        pixel[0] = value;
+        pixel[1] = value;
+        pixel[2] = value;
+        return;
+    }
+
+    let scale = value / current as f32;
+
+    for channel in pixel.0.iter_mut().take(3) {
+        *channel = (*channel as f32 * scale).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+fn compose_on(img: &mut RgbaImage, paper: [u8; 3]) {
+    for pixel in img.pixels_mut() {
+        let alpha = pixel[3] as f32 / 255.0;
+
+        for (channel, paper) in pixel.0.iter_mut().zip(paper) {
+            *channel = (*channel as f32 * alpha + paper as f32 * (1.0 - alpha)).round() as u8;
+        }
+
+        pixel[3] = 255;
+    }
+}
+
+/// Find the background by flood filling from the border. The background color is the median of
+/// the border pixels, so that a subject touching the border is not filled as well.
+fn find_background(img: &RgbaImage, tolerance: u8) -> Vec<bool> {
+    let (width, height) = img.dimensions();
+    let border = (0..width)
+        .flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]))
+        .collect::<Vec<_>>();
+
+    let mut background = [0; 3];
+
+    for (index, channel) in background.iter_mut().enumerate() {
+        let mut values = border
+            .iter()
+            .map(|(x, y)| img.get_pixel(*x, *y)[index])
+            .collect::<Vec<_>>();
+        values.sort_unstable();
+        *channel = values[values.len() / 2];
+    }
+
+    let is_background = |pixel: &Rgba<u8>| {
+        (0..3).all(|index| pixel[index].abs_diff(background[index]) <= tolerance)
+    };
+
+    let mut visited = vec![false; (width * height) as usize];
+    let mut stack = border;
+
+    while let Some((x, y)) = stack.pop() {
+        let index = (y * width + x) as usize;
+
+        if visited[index] || !is_background(img.get_pixel(x, y)) {
+            continue;
+        }
+
+        visited[index] = true;
+
+        if x > 0 {
+            stack.push((x - 1, y));
+        }
+        if x + 1 < width {
+            stack.push((x + 1, y));
+        }
+        if y > 0 {
+            stack.push((x, y - 1));
+        }
+        if y + 1 < height {
+            stack.push((x, y + 1));
+        }
+    }
+
+    visited
+}
+
+fn levels(img: &mut RgbaImage, black_point: u8, white_point: u8) {
+    let black = black_point as f32;
+    let range = (white_point as f32 - black).max(1.0);
+
+    for pixel in img.pixels_mut() {
+        for channel in pixel.0.iter_mut().take(3) {
+            *channel = ((*channel as f32 - black) * 255.0 / range)
+                .round()
+                .clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+fn equalize(img: &mut RgbaImage) {
+    let mut histogram = [0usize; 256];
+
+    for pixel in img.pixels() {
+        histogram[pixel[0].max(pixel[1]).max(pixel[2]) as usize] += 1;
+    }
+
+    let mut cdf = [0usize; 256];
+    let mut sum = 0;
+
+    for (value, count) in histogram.iter().enumerate() {
+        sum += count;
+        cdf[value] = sum;
+    }
+
+    let min = cdf.iter().copied().find(|count| *count > 0).unwrap_or(0);
+    let total = (sum - min).max(1) as f32;
+
+    for pixel in img.pixels_mut() {
+        let value = pixel[0].max(pixel[1]).max(pixel[2]) as usize;
+        set_value(pixel, (cdf[value] - min) as f32 * 255.0 / total);
+    }
+}
+
+/// Darken `img` by `weight` times the gradient magnitude of its values, normalized to the
+/// strongest edge. On dark paper points gather where the image is bright, so edges are
+/// brightened instead.
+fn enhance_edges(img: &mut RgbaImage, weight: f32, paper: [u8; 3]) {
+    let (width, height) = img.dimensions();
+
+    if width < 3 || height < 3 {
+        return;
+    }
+
+    let value = |x: u32, y: u32| {
+        let pixel = img.get_pixel(x, y);
+        pixel[0].max(pixel[1]).max(pixel[2]) as f32
+    };
+
+    let mut magnitudes = vec![0.0; (width * height) as usize];
+
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = value(x + 1, y - 1) + 2.0 * value(x + 1, y) + value(x + 1, y + 1)
+                - value(x - 1, y - 1)
+                - 2.0 * value(x - 1, y)
+                - value(x - 1, y + 1);
+            let gy = value(x - 1, y + 1) + 2.0 * value(x, y + 1) + value(x + 1, y + 1)
+                - value(x - 1, y - 1)
+                - 2.0 * value(x, y - 1)
+                - value(x + 1, y - 1);
+
+            magnitudes[(y * width + x) as usize] = (gx * gx + gy * gy).sqrt();
+        }
+    }
+
+    let max = magnitudes.iter().copied().fold(0.0, f32::max);
+
+    if max == 0.0 {
+        return;
+    }
+
+    let sign = if paper.iter().all(|channel| *channel < 128) {
+        1.0
+    } else {
+        -1.0
+    };
+
+    for (pixel, magnitude) in img.pixels_mut().zip(magnitudes) {
+        let value = pixel[0].max(pixel[1]).max(pixel[2]) as f32;
+        set_value(
+            pixel,
+            (value + sign * weight * 255.0 * magnitude / max).clamp(0.0, 255.0),
+        );
+    }
+}
+
+impl Adjustments {
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let has_alpha = img.color().has_alpha();
+        let is_rgb8 = matches!(img, DynamicImage::ImageRgb8(_));
+
+        if *self == Self::default() && is_rgb8 {
+            return img.clone();
+        }
+
+        let mut result = img.to_rgba8();
+
+        // Transparent and removed pixels are reset to the paper after all other adjustments,
+        // which would otherwise change them and place points there.
+        let mut blank = result
+            .pixels()
+            .map(|pixel| pixel[3] == 0)
+            .collect::<Vec<_>>();
+
+        if has_alpha {
+            compose_on(&mut result, self.paper);
+        }
+
+        if let Some(tolerance) = self.remove_background {
+            for (blank, background) in blank.iter_mut().zip(find_background(&result, tolerance)) {
+                *blank |= background;
+            }
+        }
+
+        if self.black_point != 0 || self.white_point != 255 {
+            levels(&mut result, self.black_point, self.white_point);
+        }
+
+        if self.equalize {
+            equalize(&mut result);
+        }
+
+        if self.brightness != 0 {
+            result = imageops::brighten(&result, self.brightness);
+        }
+
+        if self.contrast != 0.0 {
+            result = imageops::contrast(&result, self.contrast);
+        }
+
+        if let Some(sigma) = self.blur {
+            result = imageops::blur(&result, sigma);
+        }
+
+        if let Some((sigma, threshold)) = self.unsharpen {
+            result = imageops::unsharpen(&result, sigma, threshold);
+        }
+
+        if self.edges != 0.0 {
+            enhance_edges(&mut result, self.edges, self.paper);
+        }
+
+        let [r, g, b] = self.paper;
+
+        for (pixel, _) in result.pixels_mut().zip(blank).filter(|(_, blank)| *blank) {
+            *pixel = Rgba([r, g, b, 255]);
+        }
+
+        DynamicImage::ImageRgba8(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_is_applied_in_order() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 20, |x, y| {
+            Rgba([x as u8, y as u8, 0, 255])
+        }));
+
+        let geometry = Geometry {
+            crop: Some((10, 0, 20, 100)),
+            rotate: Some(Rotation::Rotate90),
+            width: Some(10),
+            ..Geometry::default()
+        };
+        let result = geometry.apply(img).unwrap();
+
+        assert_eq!(result.dimensions(), (10, 10));
+        assert_eq!(result.get_pixel(9, 9)[0], 28);
+    }
+
+    #[test]
+    fn deep_grayscale_images_are_converted() {
+        let img =
+            image::ImageBuffer::from_fn(2, 1, |x, _| image::LumaA([32896u16, x as u16 * 65535]));
+        let converted = Adjustments::default()
+            .apply(&DynamicImage::ImageLumaA16(img))
+            .to_rgba8();
+
+        assert_eq!(converted.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(converted.get_pixel(1, 0), &Rgba([128, 128, 128, 255]));
+    }
+
+    #[test]
+    fn background_is_removed() {
+        let img = RgbaImage::from_fn(5, 5, |x, y| match (x, y) {
+            (2, 2) => Rgba([0, 0, 0, 0]),
+            (1..=3, 1..=4) => Rgba([20, 20, 20, 255]),
+            _ => Rgba([180, 190, 200, 255]),
+        });
+
+        let img = DynamicImage::ImageRgba8(img);
+        let removed = Adjustments {
+            remove_background: Some(10),
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        assert_eq!(removed.get_pixel(0, 0), &Rgba([255, 255, 255, 255]));
+        assert_eq!(removed.get_pixel(2, 2), &Rgba([255, 255, 255, 255]));
+        assert_eq!(removed.get_pixel(2, 4), &Rgba([20, 20, 20, 255]));
+
+        // Light pens on black paper must not draw the background either.
+        let removed = Adjustments {
+            paper: [0, 0, 0],
+            remove_background: Some(10),
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        for (x, y) in [(0, 0), (2, 2)] {
+            let pixel = removed.get_pixel(x, y);
+            assert_eq!(pixel, &Rgba([0, 0, 0, 255]));
+            assert_eq!(
+                crate::color::to_rgb(pixel[0], pixel[1], pixel[2]),
+                (0., 0., 0.)
+            );
+        }
+    }
+
+    #[test]
+    fn paper_is_not_adjusted() {
+        let img = RgbaImage::from_fn(10, 10, |x, y| match (x, y) {
+            (3..=6, 3..=6) => Rgba([100, 100, 100, 255]),
+            (9, _) => Rgba([250, 250, 250, 255]),
+            _ => Rgba([0, 0, 0, 0]),
+        });
+
+        let adjusted = Adjustments {
+            brightness: -60,
+            contrast: -50.0,
+            remove_background: Some(10),
+            edges: 0.5,
+            ..Adjustments::default()
+        }
+        .apply(&DynamicImage::ImageRgba8(img))
+        .to_rgba8();
+
+        assert_eq!(adjusted.get_pixel(1, 1), &Rgba([255, 255, 255, 255]));
+        assert_eq!(adjusted.get_pixel(9, 5), &Rgba([255, 255, 255, 255]));
+        assert_ne!(adjusted.get_pixel(5, 5), &Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn edges_are_darkened() {
+        let img = RgbaImage::from_fn(6, 3, |x, _| {
+            let value = if x < 3 { 100 } else { 200 };
+            Rgba([value, value, value, 255])
+        });
+
+        let img = DynamicImage::ImageRgba8(img);
+
+        let enhanced = Adjustments {
+            edges: 0.5,
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        assert_eq!(enhanced.get_pixel(1, 1)[0], 100);
+        assert_eq!(enhanced.get_pixel(2, 1)[0], 0);
+        assert_eq!(enhanced.get_pixel(3, 1)[0], 73);
+        assert_eq!(enhanced.get_pixel(4, 1)[0], 200);
+
+        // Points gather where the image is bright on black paper.
+        let enhanced = Adjustments {
+            paper: [0, 0, 0],
+            edges: 0.5,
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        assert_eq!(enhanced.get_pixel(1, 1)[0], 100);
+        assert_eq!(enhanced.get_pixel(2, 1)[0], 228);
+        assert_eq!(enhanced.get_pixel(3, 1)[0], 255);
+        assert_eq!(enhanced.get_pixel(4, 1)[0], 200);
+    }
+
+    #[test]
+    fn levels_and_equalization_stretch_values() {
+        let img = RgbaImage::from_fn(4, 1, |x, _| {
+            let value = 100 + 10 * x as u8;
+            Rgba([value, value / 2, value, 255])
+        });
+        let img = DynamicImage::ImageRgba8(img);
+
+        let leveled = Adjustments {
+            black_point: 100,
+            white_point: 130,
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        assert_eq!(leveled.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(leveled.get_pixel(3, 0), &Rgba([255, 0, 255, 255]));
+
+        let equalized = Adjustments {
+            equalize: true,
+            ..Adjustments::default()
+        }
+        .apply(&img)
+        .to_rgba8();
+
+        assert_eq!(equalized.get_pixel(0, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(equalized.get_pixel(1, 0), &Rgba([85, 43, 85, 255]));
+        assert_eq!(equalized.get_pixel(3, 0), &Rgba([255, 128, 255, 255]));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::Point;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Halftone screen of a channel, a square grid rotated by `angle` in degrees and shifted by
+/// `offset` times `spacing` along both of its axes.
+///
+/// Screening the channels of a print with different angles such as 15°, 75°, 0° and 45° for
+/// cyan, magenta, yellow and black keeps their dots from colliding and avoids moiré.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Screen {
+    pub angle: f64,
+    pub offset: f64,
+    pub spacing: f64,
+}
+
+impl Screen {
+    /// Move every point to the nearest free node of the screen, or to one of the nodes around
+    /// it if that is already taken. Points without a free node nearby are dropped.
+    pub fn apply(&self, points: &[Point]) -> Vec<Point> {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let offset = self.offset * self.spacing;
+
+        // Coordinates along the axes of the screen in units of the spacing and back.
+        let to_screen = |p: &Point| {
+            let u = (p.x * cos + p.y * sin - offset) / self.spacing;
+            let v = (-p.x * sin + p.y * cos - offset) / self.spacing;
+            (u, v)
+        };
+        let to_image = |(i, j): (i64, i64)| {
+            let u = i as f64 * self.spacing + offset;
+            let v = j as f64 * self.spacing + offset;
+            Point::new(u * cos - v * sin, u * sin + v * cos)
+        };
+
+        let mut taken = HashSet::new();
+        let mut result = Vec::with_capacity(points.len());
+
+        for point in points {
+            let (u, v) = to_screen(point);
+            let (i, j) = (u.round() as i64, v.round() as i64);
+
+            let mut nodes = (-1..=1)
+                .flat_map(|di| (-1..=1).map(move |dj| (i + di, j + dj)))
+                .collect::<Vec<_>>();
+            nodes.sort_by(|a, b| {
+                let distance = |(i, j): &(i64, i64)| (*i as f64 - u).hypot(*j as f64 - v);
+                distance(a).total_cmp(&distance(b))
+            });
+
+            if let Some(node) = nodes.into_iter().find(|node| !taken.contains(node)) {
+                taken.insert(node);
+                result.push(to_image(node));
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_snap_to_rotated_grid() {
+        let screen = Screen {
+            angle: 45.0,
+            offset: 0.5,
+            spacing: 2.0,
+        };
+        let points = [
+            Point::new(0.1, 1.5),
+            Point::new(0.0, 1.4),
+            Point::new(5.0, 5.0),
+        ];
+        let screened = screen.apply(&points);
+
+        assert_eq!(screened.len(), 3);
+        assert!(screened[0].distance(&Point::new(0.0, 2f64.sqrt())) < 1e-9);
+        assert!(screened[1].distance(&screened[0]) > 1.9);
+
+        for point in screened {
+            let u = (point.x + point.y) / 2f64.sqrt() / 2.0 - 0.5;
+            assert!((u - u.round()).abs() < 1e-9);
+        }
+    }
+}
@@ -5,11 +5,10 @@ use std::path;
 use svg::node::element::path::Data;
 use svg::node::element::Circle;
 use svg::node::element::Path;
+use svg::node::element::Rectangle;
 use svg::Document;
 
-const COLORS: [&str; 4] = ["cyan", "magenta", "yellow", "black"];
-
-fn draw_path(document: Document, path: &Vec<Point>, color: &str) -> Document {
+fn draw_path(document: Document, path: &[Point], color: &str) -> Document {
     if path.is_empty() {
         return document;
     }
@@ -29,7 +28,7 @@ fn draw_path(document: Document, path: &Vec<Point>, color: &str) -> Document {
     document.add(path)
 }
 
-fn draw_points(document: Document, points: &Vec<Point>, color: &str) -> Document {
+fn draw_points(document: Document, points: &[Point], color: &str) -> Document {
     let mut document = document;
 
     for point in points {
@@ -45,15 +44,52 @@ fn draw_points(document: Document, points: &Vec<Point>, color: &str) -> Document
     document
 }
 
+/// Empty document filled with the color of `paper` if any.
+fn new_document(width: u32, height: u32, paper: Option<&str>) -> Document {
+    let document = Document::new().set("viewBox", (0, 0, width, height));
+
+    match paper {
+        Some(color) => document.add(
+            Rectangle::new()
+                .set("fill", color)
+                .set("width", width)
+                .set("height", height),
+        ),
+        None => document,
+    }
+}
+
+/// Write each of the `point_sets` with the corresponding SVG color of `colors` on `paper`,
+/// e.g. `black`, or no background.
 pub fn write_path(
     filename: &path::Path,
     point_sets: &[Vec<Point>],
+    colors: &[&str],
+    paper: Option<&str>,
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let mut document = new_document(width, height, paper);
+
+    for (path, color) in point_sets.iter().zip(colors) {
+        document = draw_path(document, path, color);
+    }
+
+    svg::save(filename, &document)?;
+
+    Ok(())
+}
+
+pub fn write_strokes(
+    filename: &path::Path,
+    strokes: &[Vec<Point>],
+    color: &str,
     width: u32,
     height: u32,
 ) -> Result<()> {
     let mut document = Document::new().set("viewBox", (0, 0, width, height));
 
-    for (path, color) in point_sets.iter().zip(COLORS.iter()) {
+    for path in strokes {
         document = draw_path(document, path, color);
     }
 
@@ -62,15 +98,18 @@ pub fn write_path(
     Ok(())
 }
 
+/// Write each of the `point_sets` with the corresponding SVG color of `colors` on `paper`.
 pub fn write_points(
     filename: &path::Path,
     point_sets: &[Vec<Point>],
+    colors: &[&str],
+    paper: Option<&str>,
     width: u32,
     height: u32,
 ) -> Result<()> {
-    let mut document = Document::new().set("viewBox", (0, 0, width, height));
+    let mut document = new_document(width, height, paper);
 
-    for (points, color) in point_sets.iter().zip(COLORS.iter()) {
+    for (points, color) in point_sets.iter().zip(colors) {
         document = draw_points(document, points, color);
     }
 
@@ -2,6 +2,7 @@ use crate::color::to_black;
 use crate::Point;
 use anyhow::{anyhow, Result};
 use image::GenericImageView;
+use std::collections::HashMap;
 use voronator::delaunator;
 
 fn weighted_centroid(points: &[delaunator::Point], img: &image::DynamicImage) -> Point {
@@ -88,3 +89,73 @@ pub fn move_points(points: Vec<Point>, img: &image::DynamicImage) -> Result<Vec<
         .map(|c| weighted_centroid(c.points(), img))
         .collect::<Vec<_>>())
 }
+
+/// Push the points of each channel that are closer than `min_distance` to a point of an earlier
+/// channel away from it, keeping them within `width` and `height`. Where the points are too
+/// dense for that, they end up as far apart as a few attempts allow.
+pub fn keep_apart(point_sets: &mut [Vec<Point>], min_distance: f64, width: u32, height: u32) {
+    const ATTEMPTS: usize = 8;
+
+    let cell = |p: &Point| {
+        (
+            (p.x / min_distance).floor() as i64,
+            (p.y / min_distance).floor() as i64,
+        )
+    };
+    let mut grid: HashMap<(i64, i64), Vec<Point>> = HashMap::new();
+
+    for points in point_sets.iter_mut() {
+        for point in points.iter_mut() {
+            for _ in 0..ATTEMPTS {
+                let (i, j) = cell(point);
+                let closest = (-1..=1)
+                    .flat_map(|di| (-1..=1).map(move |dj| (i + di, j + dj)))
+                    .filter_map(|c| grid.get(&c))
+                    .flatten()
+                    .map(|neighbor| (point.distance(neighbor), *neighbor))
+                    .filter(|(distance, _)| *distance < min_distance)
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+
+                let (distance, neighbor) = match closest {
+                    Some(closest) => closest,
+                    None => break,
+                };
+
+                let direction = if distance > 0.0 {
+                    (*point - neighbor) / distance
+                } else {
+                    Point::new(1.0, 0.0)
+                };
+                *point = neighbor + direction * min_distance;
+                point.x = point.x.clamp(0.0, width as f64);
+                point.y = point.y.clamp(0.0, height as f64);
+            }
+        }
+
+        for point in points.iter() {
+            grid.entry(cell(point)).or_default().push(*point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_are_kept_apart() {
+        let mut point_sets = vec![
+            vec![Point::new(5.0, 5.0)],
+            vec![
+                Point::new(5.5, 5.0),
+                Point::new(5.0, 5.0),
+                Point::new(9.0, 9.0),
+            ],
+        ];
+        keep_apart(&mut point_sets, 2.0, 10, 10);
+
+        assert!(point_sets[1][0].distance(&Point::new(7.0, 5.0)) < 1e-9);
+        assert!(point_sets[1][1].distance(&Point::new(7.0, 5.0)) < 1e-9);
+        assert!(point_sets[1][2].distance(&Point::new(9.0, 9.0)) < 1e-9);
+    }
+}
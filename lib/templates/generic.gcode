@@ -0,0 +1,36 @@
+; Generated with `gcode-converter` for calibration
+;
+;     kinematics: {{ machine }}
+;  drawing_width: {{ calibration.drawing_width }}
+; drawing_height: {{ calibration.drawing_height }}
+{%- if calibration.max_segment_length is not none %}
+; max_segment_length: {{ calibration.max_segment_length }}
+{%- endif %}
+;         margin: {{ calibration.margin }}
+;
+{%- for layer in layers %}
+{%- if pen_change %}
+{%- if pen_up %}
+{{ pen_up }}
+{%- endif %}
+{{ pen_change_command|replace("{name}", layer.name) }}
+{%- endif %}
+{%- for stroke in layer.strokes %}
+{%- for point in stroke.points %}
+{%- if loop.first %}
+{%- if pen_up %}
+{{ pen_up }}
+{%- endif %}
+g0 x{{ point.x }} y{{ point.y }}
+{%- if pen_down %}
+{{ pen_down }}
+{%- endif %}
+{%- else %}
+g1 x{{ point.x }} y{{ point.y }}{% if loop.index == 2 %} f{{ feed_rate }}{% endif %}
+{%- endif %}
+{%- endfor %}
+{%- endfor %}
+{%- endfor %}
+{%- if pen_up %}
+{{ pen_up }}
+{%- endif %}
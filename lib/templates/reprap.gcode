@@ -0,0 +1,22 @@
+; Generated with `gcode-converter` for RepRap
+;
+; kinematics: {{ machine }}
+; {{ stats.layers }} layers, {{ stats.strokes }} strokes, {{ stats.points }} points
+;
+G21
+G90
+{{ pen_up }}
+{%- for layer in layers %}
+{%- if pen_change %}
+{{ pen_change_command|replace("{name}", layer.name) }}
+{%- endif %}
+{%- for stroke in layer.strokes %}
+G0 X{{ stroke.points[0].x|round(3) }} Y{{ stroke.points[0].y|round(3) }}
+{{ pen_down }}
+{%- for point in stroke.points[1:] %}
+G1 X{{ point.x|round(3) }} Y{{ point.y|round(3) }}{% if loop.first %} F{{ feed_rate }}{% endif %}
+{%- endfor %}
+{{ pen_up }}
+{%- endfor %}
+{%- endfor %}
+G0 X0 Y0
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use image::io::Reader;
 use image::GenericImageView;
+use inkdrop::preprocess::Adjustments;
 use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 use yew::worker::{Agent, AgentLink, HandlerId, Public};
@@ -72,6 +73,9 @@ impl Agent for Worker {
                     .decode()
                     .unwrap();
 
+                // Compose transparent regions onto white.
+                let image = Adjustments::default().apply(&image);
+
                 let (width, height) = image.dimensions();
 
                 let mut point_sets = inkdrop::sample_points(&image, data.num_points, 1.0, false);